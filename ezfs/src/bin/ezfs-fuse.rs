@@ -0,0 +1,61 @@
+//! Serves a RustEzFs image through FUSE.
+//!
+//! Usage: `ezfs-fuse [-o OPTIONS] IMAGE MOUNTPOINT`, where `OPTIONS` are ezfs mount options. Runs
+//! in the foreground until the mountpoint is unmounted.
+
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use ezfs::RustEzFs;
+use kernel::block::FileDevice;
+use kernel::fuse::{self, Session};
+use kernel::types::Result;
+use kernel::vfs::Vfs;
+
+const USAGE: &str = "usage: ezfs-fuse [-o OPTIONS] IMAGE MOUNTPOINT";
+
+fn serve(image: &str, mountpoint: &Path, options: &str) -> Result {
+    let vfs = Vfs::<RustEzFs>::mount(Some(Arc::new(FileDevice::open(image)?)), options)?;
+    let mut dev = fuse::mount_fuse::<RustEzFs>(image, mountpoint)?;
+
+    let served = Session::new(&vfs).and_then(|mut session| session.serve(&mut dev));
+    if served.is_err() {
+        let _ = fuse::unmount_fuse(mountpoint);
+    }
+
+    let unmounted = vfs.umount();
+    served.and(unmounted)
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let mut options = String::new();
+    let mut paths = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => match args.next() {
+                Some(opts) => options = opts,
+                None => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::FAILURE;
+                }
+            },
+            _ => paths.push(arg),
+        }
+    }
+
+    let [image, mountpoint] = paths.as_slice() else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    match serve(image, Path::new(mountpoint), &options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ezfs-fuse: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
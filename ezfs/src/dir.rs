@@ -1,6 +1,7 @@
 use crate::defs::{EZFS_FILENAME_BUF_SIZE, EZFS_MAX_CHILDREN};
-use core::ops::Deref;
-// use kernel::transmute::FromBytes;
+use core::mem::size_of;
+use core::ops::{Deref, DerefMut};
+use kernel::transmute::{AsBytes, FromBytes};
 
 #[repr(C)]
 pub(crate) struct EzfsDirEntry {
@@ -9,6 +10,14 @@ pub(crate) struct EzfsDirEntry {
     filename: [u8; EZFS_FILENAME_BUF_SIZE],
 }
 
+const _: () = assert!(size_of::<EzfsDirEntry>() == 128);
+
+// SAFETY: EzfsDirEntry only contains integers, for which any bit pattern is valid
+unsafe impl FromBytes for EzfsDirEntry {}
+
+// SAFETY: EzfsDirEntry only contains integers and its fields leave no padding
+unsafe impl AsBytes for EzfsDirEntry {}
+
 impl EzfsDirEntry {
     pub(crate) fn inode_no(&self) -> u64 {
         self.inode_no
@@ -18,6 +27,26 @@ impl EzfsDirEntry {
         self.active != 0
     }
 
+    /// Makes this entry an active link named `name` to inode `inode_no`.
+    ///
+    /// `name` must fit in the filename buffer.
+    pub(crate) fn set(&mut self, inode_no: u64, name: &[u8]) {
+        self.inode_no = inode_no;
+        self.active = 1;
+        self.set_filename(name);
+    }
+
+    pub(crate) fn set_filename(&mut self, name: &[u8]) {
+        self.filename.fill(0);
+        self.filename[..name.len()].copy_from_slice(name);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.inode_no = 0;
+        self.active = 0;
+        self.filename.fill(0);
+    }
+
     pub(crate) fn filename(&self) -> &[u8] {
         let len = self
             .filename
@@ -42,5 +71,14 @@ impl Deref for DirEntryStore {
     }
 }
 
-// TODO: Add Safety
-// unsafe impl FromBytes for DirEntryStore {}
+impl DerefMut for DirEntryStore {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.dir_entries
+    }
+}
+
+// SAFETY: EzfsDirEntry is FromBytes, so array of them is too
+unsafe impl FromBytes for DirEntryStore {}
+
+// SAFETY: EzfsDirEntry is AsBytes, so array of them is too
+unsafe impl AsBytes for DirEntryStore {}
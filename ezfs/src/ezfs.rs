@@ -5,49 +5,367 @@
 mod defs;
 mod dir;
 mod inode;
+mod mkfs;
+mod options;
 mod sb;
+#[cfg(test)]
+mod tests;
 #[cfg(kani)]
 mod verification;
 
+pub use mkfs::format;
+
 use crate::dir::{DirEntryStore, EzfsDirEntry};
 use crate::inode::{EzfsInode, InodeStore};
+use crate::options::{Errors, EzfsMountOpts};
 use crate::sb::{EzfsSuperblock, EzfsSuperblockDisk};
 use defs::*;
-// use kernel::dentry;
-use kernel::fs::{File, FileSystem, Offset};
-use kernel::inode::{INode, INodeState, Mapper};
+use kernel::PAGE_SIZE;
+use kernel::address_space::{self, Folio};
+use kernel::buffer::{sb_bread, sb_set_blocksize};
+use kernel::cred;
+use kernel::dentry;
+use kernel::file::{self, File};
+use kernel::fs::{Context, FileSystem, Offset, ParamSpec};
+use kernel::inode::{
+    INode, INodeState, MAY_EXEC, MAY_WRITE, Mapper, Ops, Params, generic_permission,
+};
 // use kernel::prelude::*;
-use kernel::sb::{New, SuperBlock, Type as SuperType};
-// use kernel::time::UNIX_EPOCH;
-// use kernel::transmute::FromBytes;
-use kernel::fs;
+use kernel::sb::{New, Ready, SB_NOATIME, StatFs, SuperBlock, Type as SuperType};
+use kernel::time::NSEC_PER_SEC;
+use kernel::transmute::{AsBytes, FromBytes};
+use kernel::types::code::{
+    EEXIST, EFBIG, EINVAL, EIO, EISDIR, ENAMETOOLONG, ENOENT, ENOSPC, ENOTDIR, ENOTEMPTY, EPERM,
+    EUCLEAN,
+};
 use kernel::types::{ARef, Error, Locked, Result};
+use kernel::uapi::{self, S_IFDIR, S_IFREG, mode_t};
+use kernel::user;
 
-use core::marker::{PhantomData, Send, Sync};
 use core::mem::size_of;
 use std::ops::Range;
 
-struct RustEzFs;
+pub struct RustEzFs;
 
 impl RustEzFs {
+    /// Handles finding the disk corrupted as `errors=` says, returning the error to fail with.
+    fn corrupted(sb: &SuperBlock<Self>) -> Error {
+        match sb.data().opts.lock().map(|opts| opts.errors) {
+            Ok(Errors::RemountRo) => sb.force_rdonly(),
+            Ok(Errors::Panic) => panic!("ezfs: the disk is corrupted"),
+            _ => {}
+        }
+
+        EUCLEAN
+    }
+
+    fn iget(sb: &SuperBlock<Self, Ready>, ino: usize) -> Result<ARef<INode<Self>>> {
+        let inode = match sb.get_or_create_inode(ino)? {
+            INodeState::Existing(inode) => return Ok(inode),
+            INodeState::Uninitilized(new) => new,
+        };
+
+        let h = sb.data();
+
+        if !Self::inode_allocated(h, ino)? {
+            return Err(ENOENT);
+        }
+
+        let bh = sb_bread(sb, EZFS_INODE_STORE_DATABLOCK_NUMBER as u64)?;
+        let data = bh.data();
+        let bytes = data.get(..size_of::<InodeStore>()).ok_or(EIO)?;
+        let inode_store = InodeStore::from_bytes(bytes).ok_or(EIO)?;
+
+        let ezfs_inode = *ino
+            .checked_sub(EZFS_ROOT_INODE_NUMBER)
+            .and_then(|i| inode_store.get(i))
+            .ok_or_else(|| Self::corrupted(sb))?;
+        drop(data);
+
+        let mut inode = inode;
+        Self::set_ops(&mut inode, ezfs_inode.mode().into());
+        inode.init(Self::params(ezfs_inode)?)
+    }
+
+    /// The VFS view of `disk_inode`.
+    fn params(disk_inode: EzfsInode) -> Result<Params<EzfsInode>> {
+        Ok(Params {
+            mode: disk_inode.mode().into(),
+            size: disk_inode.file_size(),
+            blocks: disk_inode.nblocks(),
+            nlink: disk_inode.nlink(),
+            uid: disk_inode.uid(),
+            gid: disk_inode.gid(),
+            atime: disk_inode.atime()?,
+            mtime: disk_inode.mtime()?,
+            ctime: disk_inode.ctime()?,
+            value: disk_inode,
+        })
+    }
+
+    /// Records a change to the contents of `inode`.
+    fn touch(inode: &INode<Self>) {
+        let now = inode.current_time();
+
+        inode.set_mtime(now);
+        inode.set_ctime(now);
+    }
+
+    /// Records an access to `inode`, marking it dirty if its access time changed, unless the
+    /// filesystem is read-only or was mounted with `noatime`.
+    fn accessed(inode: &INode<Self>) {
+        let now = inode.current_time();
+        let sb = inode.super_block();
+        let noatime = sb.flags() & SB_NOATIME != 0 || sb.is_rdonly();
+
+        if !noatime && inode.atime() != now {
+            inode.set_atime(now);
+            inode.mark_dirty();
+        }
+    }
+
+    fn set_ops(new: &mut kernel::inode::New<Self>, mode: mode_t) {
+        new.set_iops(Ops::new::<Self>());
+
+        if uapi::s_isdir(mode) {
+            new.set_fops(file::Ops::new::<Self>());
+        } else {
+            new.set_fops(file::Ops::new::<EzfsFile>());
+            new.set_aops(address_space::Ops::new::<EzfsFile>());
+        }
+    }
+
+    fn block_offset(blk: u64) -> Result<Offset> {
+        blk.checked_mul(EZFS_BLOCK_SIZE as u64)
+            .ok_or(EIO)?
+            .try_into()
+            .map_err(|_| EIO)
+    }
+
+    /// Returns the index and inode number of the active entry `name` in directory `dir`.
+    fn find_entry(dir: &INode<Self>, name: &[u8]) -> Result<Option<(usize, u64)>> {
+        let h = dir.super_block().data();
+
+        let mapped = h
+            .mapper
+            .mapped_folio(Self::block_offset(dir.data().data_blk_num())?)?;
+        let bytes = mapped.get(..size_of::<DirEntryStore>()).ok_or(EIO)?;
+        let dir_entries = DirEntryStore::from_bytes(bytes).ok_or(EIO)?;
+
+        Ok(dir_entries
+            .iter()
+            .position(|x| x.filename() == name && x.is_active())
+            .map(|idx| (idx, dir_entries[idx].inode_no())))
+    }
+
+    /// Runs `f` on the entries of directory `dir` and writes them back, along with the updated
+    /// times of `dir`.
+    fn update_entries<R>(dir: &INode<Self>, f: impl FnOnce(&mut DirEntryStore) -> R) -> Result<R> {
+        let h = dir.super_block().data();
+
+        let mut mapped = h
+            .mapper
+            .mapped_folio_mut(Self::block_offset(dir.data().data_blk_num())?)?;
+        let bytes = mapped.get_mut(..size_of::<DirEntryStore>()).ok_or(EIO)?;
+        let dir_entries = DirEntryStore::from_bytes_mut(bytes).ok_or(EIO)?;
+
+        let ret = f(dir_entries);
+        mapped.flush()?;
+
+        Self::touch(dir);
+        dir.mark_dirty();
+
+        Ok(ret)
+    }
+
+    fn add_entry(dir: &INode<Self>, name: &[u8], ino: usize) -> Result {
+        Self::update_entries(dir, |entries| {
+            let entry = entries.iter_mut().find(|x| !x.is_active()).ok_or(ENOSPC)?;
+            entry.set(ino as u64, name);
+            Ok(())
+        })?
+    }
+
+    fn remove_entry(dir: &INode<Self>, idx: usize) -> Result {
+        Self::update_entries(dir, |entries| entries[idx].clear())
+    }
+
+    fn is_empty_dir(dir: &INode<Self>) -> Result<bool> {
+        let h = dir.super_block().data();
+
+        let mapped = h
+            .mapper
+            .mapped_folio(Self::block_offset(dir.data().data_blk_num())?)?;
+        let bytes = mapped.get(..size_of::<DirEntryStore>()).ok_or(EIO)?;
+        let dir_entries = DirEntryStore::from_bytes(bytes).ok_or(EIO)?;
+
+        Ok(!dir_entries.iter().any(|x| x.is_active()))
+    }
+
+    fn check_name(name: &[u8]) -> Result {
+        if name.len() > EZFS_FILENAME_LENGTH {
+            return Err(ENAMETOOLONG);
+        }
+
+        if name.is_empty() || name.contains(&b'/') || name.contains(&0) {
+            return Err(EINVAL);
+        }
+
+        Ok(())
+    }
+
+    /// Allocates an inode and its first data block and links it into `dir` as `name`.
+    fn new_child(dir: &INode<Self>, name: &[u8], mode: mode_t) -> Result<ARef<INode<Self>>> {
+        Self::check_name(name)?;
+
+        if Self::find_entry(dir, name)?.is_some() {
+            return Err(EEXIST);
+        }
+        generic_permission(dir, MAY_WRITE | MAY_EXEC)?;
+
+        let sb = dir.super_block();
+        let h = sb.data();
+
+        let ino = Self::allocate_inode(h)?;
+        let blk = match Self::allocate_data_block(h) {
+            Ok(blk) => blk,
+            Err(e) => {
+                Self::deallocate_inode(h, ino)?;
+                return Err(e);
+            }
+        };
+
+        let res = (|| {
+            Self::zero_block(h, blk)?;
+
+            let opts = *h.opts.lock()?;
+            let cred = cred::current();
+            let mut disk_inode = EzfsInode::new(
+                mode.try_into()?,
+                opts.uid.unwrap_or(cred.fsuid),
+                opts.gid.unwrap_or(cred.fsgid),
+                blk,
+            );
+            if uapi::s_isdir(mode) {
+                disk_inode.set_nlink(2);
+                disk_inode.set_file_size(EZFS_BLOCK_SIZE as u64);
+            }
+            let now = dir.current_time();
+            disk_inode.set_times(now, now, now);
+
+            let params = Self::params(disk_inode)?;
+
+            let new = match sb.get_or_create_inode(ino)? {
+                INodeState::Uninitilized(new) => new,
+                INodeState::Existing(_) => return Err(EIO),
+            };
+
+            // Link the entry first so that a failure leaves no inode behind in the cache.
+            Self::add_entry(dir, name, ino)?;
+
+            let mut new = new;
+            Self::set_ops(&mut new, mode);
+            let inode = new.init(params)?;
+
+            inode.mark_dirty();
+            Ok(inode)
+        })();
+
+        if res.is_err() {
+            Self::deallocate_data_blocks(h, blk..blk + 1)?;
+            Self::deallocate_inode(h, ino)?;
+        }
+
+        res
+    }
+
+    /// Extends the contiguous run of data blocks of `inode` to `nblocks` blocks.
+    ///
+    /// ezfs does not move files, so this fails with `ENOSPC` if a block right after the file is
+    /// taken.
+    fn grow(inode: &INode<Self>, nblocks: u64) -> Result {
+        let cur = inode.blocks();
+        if nblocks <= cur {
+            return Ok(());
+        }
+
+        let h = inode.super_block().data();
+        let max_blocks = Self::max_blocks(h)?;
+        let first = inode
+            .data()
+            .data_blk_num()
+            .checked_sub(EZFS_ROOT_DATABLOCK_NUMBER as u64)
+            .ok_or_else(|| Self::corrupted(inode.super_block()))?;
+
+        {
+            let mut sb_data = h.data.lock()?;
+
+            let wanted = first + cur..first + nblocks;
+            if wanted.end > max_blocks
+                || wanted
+                    .clone()
+                    .any(|idx| sb_data.free_data_blocks.is_set(idx))
+            {
+                return Err(ENOSPC);
+            }
+
+            for idx in wanted {
+                sb_data.free_data_blocks.set_bit(idx)?;
+            }
+        }
+
+        let blk = inode.data().data_blk_num();
+        let zeroed = (blk + cur..blk + nblocks).try_for_each(|blk| Self::zero_block(h, blk));
+
+        if zeroed.is_err() {
+            Self::deallocate_data_blocks(h, blk + cur..blk + nblocks)?;
+            return zeroed;
+        }
+
+        inode.set_blocks(nblocks);
+        Ok(())
+    }
+
+    fn zero_block(h: &EzfsSuperblock, blk: u64) -> Result {
+        let mut mapped = h.mapper.mapped_folio_mut(Self::block_offset(blk)?)?;
+
+        mapped.fill(0);
+        mapped.flush()
+    }
+
+    /// Drops one link to `inode`; its blocks and inode number are released when it is evicted.
+    fn drop_link(inode: &INode<Self>) {
+        let nlink = if uapi::s_isdir(inode.mode()) {
+            0
+        } else {
+            inode.nlink().saturating_sub(1)
+        };
+
+        inode.set_nlink(nlink);
+        inode.set_ctime(inode.current_time());
+
+        if nlink > 0 {
+            inode.mark_dirty();
+        }
+    }
+
     fn max_blocks(sb: &EzfsSuperblock) -> Result<u64> {
-        Ok((sb.disk_blocks.checked_sub(2).ok_or(Error(21))?).min(EZFS_MAX_DATA_BLKS as u64))
+        Ok((sb.disk_blocks.checked_sub(2).ok_or(EUCLEAN)?).min(EZFS_MAX_DATA_BLKS as u64))
     }
 
     fn inode_allocated(ezfs_sb: &EzfsSuperblock, ino: usize) -> Result<bool> {
-        let sb_data = ezfs_sb.data.lock().map_err(|_| Error(21))?;
+        let sb_data = ezfs_sb.data.lock()?;
 
         let idx: u64 = ino
             .checked_sub(EZFS_ROOT_INODE_NUMBER)
-            .ok_or(Error(21))?
-            .try_into()
-            .map_err(|_| Error(21))?;
+            .ok_or(EINVAL)?
+            .try_into()?;
 
         Ok(sb_data.free_inodes.is_set(idx))
     }
 
     fn allocate_inode(sb: &EzfsSuperblock) -> Result<usize> {
-        let mut sb_data = sb.data.lock().map_err(|_| Error(21))?;
+        let mut sb_data = sb.data.lock()?;
 
         for idx in 0..EZFS_MAX_INODES {
             if !sb_data.free_inodes.is_set(idx as u64) {
@@ -56,26 +374,26 @@ impl RustEzFs {
             }
         }
 
-        Err(Error(21))
+        Err(ENOSPC)
     }
 
     fn deallocate_inode(ezfs_sb: &EzfsSuperblock, ino: usize) -> Result {
-        let mut sb_data = ezfs_sb.data.lock().map_err(|_| Error(21))?;
+        let mut sb_data = ezfs_sb.data.lock()?;
 
         sb_data
             .free_inodes
-            .clear_bit(ino.checked_sub(EZFS_ROOT_INODE_NUMBER).ok_or(Error(22))? as u64)?;
+            .clear_bit(ino.checked_sub(EZFS_ROOT_INODE_NUMBER).ok_or(EINVAL)? as u64)?;
 
         Ok(())
     }
 
     fn deallocate_data_blocks(ezfs_sb: &EzfsSuperblock, range: Range<u64>) -> Result {
-        let mut sb_data = ezfs_sb.data.lock().map_err(|_| Error(21))?;
+        let mut sb_data = ezfs_sb.data.lock()?;
 
         for data_blk in range {
             let blk_idx = data_blk
                 .checked_sub(EZFS_ROOT_DATABLOCK_NUMBER as u64)
-                .ok_or(Error(21))?;
+                .ok_or(EINVAL)?;
 
             sb_data.free_data_blocks.clear_bit(blk_idx)?;
             sb_data.zero_data_blocks.clear_bit(blk_idx)?;
@@ -87,7 +405,7 @@ impl RustEzFs {
     fn allocate_data_block(ezfs_sb: &EzfsSuperblock) -> Result<u64> {
         let max_blocks = Self::max_blocks(ezfs_sb)?;
 
-        let mut sb_data = ezfs_sb.data.lock().map_err(|_| Error(21))?;
+        let mut sb_data = ezfs_sb.data.lock()?;
 
         for idx in 0..max_blocks {
             if !sb_data.free_data_blocks.is_set(idx) {
@@ -96,7 +414,7 @@ impl RustEzFs {
             }
         }
 
-        Err(Error(21))
+        Err(ENOSPC)
     }
 }
 
@@ -105,156 +423,576 @@ impl FileSystem for RustEzFs {
     type INodeData = EzfsInode;
     const NAME: &str = "rustezfs";
     const SUPER_TYPE: SuperType = SuperType::BlockDev;
+    const PARAMETERS: &[ParamSpec] = options::PARAMETERS;
 
     fn fill_super(
         sb: &mut SuperBlock<Self, New>,
+        ctx: &Context,
         mapper: Option<Mapper<Self>>,
     ) -> Result<Self::Data> {
         let Some(mapper) = mapper else {
-            return Err(Error(22));
+            return Err(EINVAL);
         };
 
-        // ASSUME: we succefully read the ezfs superblock from disk
-        let disk_sb = EzfsSuperblockDisk::default();
+        sb_set_blocksize(sb, EZFS_BLOCK_SIZE as u32)?;
+
+        let bh = sb_bread(sb, EZFS_SUPERBLOCK_DATABLOCK_NUMBER as u64)?;
+        let data = bh.data();
+        let bytes = data.get(..size_of::<EzfsSuperblockDisk>()).ok_or(EIO)?;
+        let disk_sb = EzfsSuperblockDisk::from_bytes(bytes).ok_or(EIO)?;
 
         if disk_sb.magic() != EZFS_MAGIC_NUMBER as u64 {
-            return Err(Error(22));
+            return Err(EINVAL);
         }
 
-        let ezfs_sb = Box::new(EzfsSuperblock::new(disk_sb, mapper));
+        let opts = EzfsMountOpts::default().apply(ctx)?;
+        let ezfs_sb = Box::new(EzfsSuperblock::new(disk_sb, opts, mapper));
 
-        sb.set_magic(EZFS_MAGIC_NUMBER);
+        sb.set_magic(ezfs_sb.magic() as usize)
+            .set_maxbytes(Self::max_blocks(&ezfs_sb)? * EZFS_BLOCK_SIZE as u64);
+        sb.set_time_gran(NSEC_PER_SEC)?;
+        sb.set_ops(kernel::sb::Ops::new::<Self>());
 
         Ok(ezfs_sb)
     }
 
-    // fn init_root(sb: &SuperBlock<Self>) -> Result<dentry::Root<Self>> {
-    //
-    // }
-    // fn init_root(sb: &SuperBlock<Self>) -> Result<dentry::Root<Self>> {
-    //     let inode = Self::iget(sb, EZFS_ROOT_INODE_NUMBER)?;
-    //     dentry::Root::try_new(inode)
-    // }
+    fn init_root(sb: &SuperBlock<Self>) -> Result<dentry::Root<Self>> {
+        let inode = Self::iget(sb, EZFS_ROOT_INODE_NUMBER)?;
+        dentry::Root::try_new(inode)
+    }
+
+    #[cfg(kani)]
+    fn kani_existing_inode(mut new: kernel::inode::New<Self>) -> Result<ARef<INode<Self>>> {
+        let disk_inode: EzfsInode = kani::any();
+
+        Self::set_ops(&mut new, disk_inode.mode().into());
+        new.init(Self::params(disk_inode)?)
+    }
+}
+
+impl kernel::sb::Operations for RustEzFs {
+    type FileSystem = Self;
+
+    /// Writes the attributes of `inode` back to its slot in the inode store.
+    fn write_inode(inode: &INode<Self>, wait: bool) -> Result {
+        let bh = sb_bread(
+            inode.super_block(),
+            EZFS_INODE_STORE_DATABLOCK_NUMBER as u64,
+        )?;
+        let mut data = bh.data();
+        let bytes = data.get_mut(..size_of::<InodeStore>()).ok_or(EIO)?;
+        let inode_store = InodeStore::from_bytes_mut(bytes).ok_or(EIO)?;
+
+        let idx = inode
+            .ino()
+            .checked_sub(EZFS_ROOT_INODE_NUMBER)
+            .ok_or(EINVAL)?;
+        let disk_inode = inode_store.get_mut(idx).ok_or(EINVAL)?;
+
+        *disk_inode = *inode.data();
+        disk_inode.set_mode(inode.mode().try_into()?);
+        disk_inode.set_owner(inode.uid(), inode.gid());
+        disk_inode.set_nlink(inode.nlink());
+        disk_inode.set_file_size(inode.size());
+        disk_inode.set_nblocks(inode.blocks());
+        disk_inode.set_times(inode.atime(), inode.mtime(), inode.ctime());
+        drop(data);
+
+        bh.mark_dirty();
+        if wait {
+            bh.sync_dirty_buffer()?;
+        }
+
+        Ok(())
+    }
+
+    /// Serializes the allocation bitmaps back into the on-disk superblock.
+    fn sync_fs(sb: &SuperBlock<Self>, wait: bool) -> Result {
+        let disk_sb = sb.data().to_disk()?;
+
+        let bh = sb_bread(sb, EZFS_SUPERBLOCK_DATABLOCK_NUMBER as u64)?;
+        bh.data()
+            .get_mut(..size_of::<EzfsSuperblockDisk>())
+            .ok_or(EIO)?
+            .copy_from_slice(disk_sb.as_bytes());
+        bh.mark_dirty();
+
+        if wait {
+            bh.sync_dirty_buffer()?;
+        }
+
+        Ok(())
+    }
+
+    /// Releases the blocks and inode number of inodes that have no links left.
+    fn evict_inode(inode: &INode<Self>) {
+        if inode.nlink() > 0 {
+            return;
+        }
+
+        let h = inode.super_block().data();
+        let blk = inode.data().data_blk_num();
+
+        // Eviction cannot fail; the bitmaps only reject indices out of range.
+        let _ = Self::deallocate_data_blocks(h, blk..blk + inode.blocks());
+        let _ = Self::deallocate_inode(h, inode.ino());
+    }
+
+    fn statfs(sb: &SuperBlock<Self>) -> Result<StatFs> {
+        let h = sb.data();
+        let blocks = Self::max_blocks(h)?;
+        let sb_data = h.data.lock()?;
+
+        let bfree = (0..blocks)
+            .filter(|&idx| !sb_data.free_data_blocks.is_set(idx))
+            .count() as u64;
+        let ffree = (0..EZFS_MAX_INODES as u64)
+            .filter(|&idx| !sb_data.free_inodes.is_set(idx))
+            .count() as u64;
+
+        Ok(StatFs {
+            magic: sb.magic(),
+            bsize: EZFS_BLOCK_SIZE as u64,
+            blocks,
+            bfree,
+            bavail: bfree,
+            files: EZFS_MAX_INODES as u64,
+            ffree,
+            namelen: EZFS_FILENAME_LENGTH as u64,
+        })
+    }
+
+    fn remount_fs(sb: &SuperBlock<Self>, ctx: &Context) -> Result {
+        let mut opts = sb.data().opts.lock()?;
+
+        *opts = opts.apply(ctx)?;
+        Ok(())
+    }
+
+    fn show_options(sb: &SuperBlock<Self>, out: &mut String) -> Result {
+        sb.data().opts.lock()?.show(out)
+    }
+}
+
+impl kernel::inode::Operations for RustEzFs {
+    type FileSystem = Self;
+
+    fn lookup(
+        parent: &Locked<&INode<Self::FileSystem>, kernel::inode::ReadSem>,
+        dentry: dentry::Unhashed<'_, Self::FileSystem>,
+    ) -> Result<Option<ARef<dentry::DEntry<Self::FileSystem>>>> {
+        let sb = parent.super_block();
+
+        let name = dentry.name();
+
+        if name.len() > EZFS_FILENAME_LENGTH {
+            return Err(ENAMETOOLONG);
+        }
+
+        let inode = if let Some((_, ino)) = Self::find_entry(parent, name)? {
+            Some(Self::iget(
+                sb,
+                ino.try_into().map_err(|_| Self::corrupted(sb))?,
+            )?)
+        } else {
+            None
+        };
+
+        dentry.splice_alias(inode)
+    }
+
+    fn create(
+        dir: &Locked<&INode<Self::FileSystem>, kernel::inode::WriteSem>,
+        name: &[u8],
+        mode: mode_t,
+    ) -> Result<ARef<INode<Self::FileSystem>>> {
+        Self::new_child(dir, name, uapi::with_perm(S_IFREG, mode))
+    }
+
+    fn mkdir(
+        dir: &Locked<&INode<Self::FileSystem>, kernel::inode::WriteSem>,
+        name: &[u8],
+        mode: mode_t,
+    ) -> Result<ARef<INode<Self::FileSystem>>> {
+        let inode = Self::new_child(dir, name, uapi::with_perm(S_IFDIR, mode))?;
+
+        dir.set_nlink(dir.nlink() + 1);
+        dir.mark_dirty();
+
+        Ok(inode)
+    }
+
+    fn unlink(
+        dir: &Locked<&INode<Self::FileSystem>, kernel::inode::WriteSem>,
+        name: &[u8],
+    ) -> Result {
+        let (idx, ino) = Self::find_entry(dir, name)?.ok_or(ENOENT)?;
+        generic_permission(dir, MAY_WRITE | MAY_EXEC)?;
+        let inode = Self::iget(
+            dir.super_block(),
+            ino.try_into()
+                .map_err(|_| Self::corrupted(dir.super_block()))?,
+        )?;
+
+        if uapi::s_isdir(inode.mode()) {
+            return Err(EISDIR);
+        }
+
+        Self::remove_entry(dir, idx)?;
+        Self::drop_link(&inode);
+
+        Ok(())
+    }
+
+    fn rmdir(
+        dir: &Locked<&INode<Self::FileSystem>, kernel::inode::WriteSem>,
+        name: &[u8],
+    ) -> Result {
+        let (idx, ino) = Self::find_entry(dir, name)?.ok_or(ENOENT)?;
+        generic_permission(dir, MAY_WRITE | MAY_EXEC)?;
+        let inode = Self::iget(
+            dir.super_block(),
+            ino.try_into()
+                .map_err(|_| Self::corrupted(dir.super_block()))?,
+        )?;
+
+        if !uapi::s_isdir(inode.mode()) {
+            return Err(ENOTDIR);
+        }
+
+        if !Self::is_empty_dir(&inode)? {
+            return Err(ENOTEMPTY);
+        }
+
+        Self::remove_entry(dir, idx)?;
+        Self::drop_link(&inode);
+
+        dir.set_nlink(dir.nlink().saturating_sub(1));
+        dir.mark_dirty();
+
+        Ok(())
+    }
+
+    fn rename(
+        old_dir: &Locked<&INode<Self::FileSystem>, kernel::inode::WriteSem>,
+        old_name: &[u8],
+        new_dir: &Locked<&INode<Self::FileSystem>, kernel::inode::WriteSem>,
+        new_name: &[u8],
+        flags: u32,
+    ) -> Result {
+        if flags != 0 {
+            return Err(EINVAL);
+        }
+
+        Self::check_name(new_name)?;
+
+        let sb = old_dir.super_block();
+        let (old_idx, ino) = Self::find_entry(old_dir, old_name)?.ok_or(ENOENT)?;
+        generic_permission(old_dir, MAY_WRITE | MAY_EXEC)?;
+        generic_permission(new_dir, MAY_WRITE | MAY_EXEC)?;
+        let inode = Self::iget(sb, ino.try_into().map_err(|_| Self::corrupted(sb))?)?;
+        let is_dir = uapi::s_isdir(inode.mode());
+
+        if let Some((new_idx, target_ino)) = Self::find_entry(new_dir, new_name)? {
+            if target_ino == ino {
+                return Ok(());
+            }
+
+            let target = Self::iget(sb, target_ino.try_into().map_err(|_| Self::corrupted(sb))?)?;
+            let target_is_dir = uapi::s_isdir(target.mode());
+
+            match (is_dir, target_is_dir) {
+                (true, false) => return Err(ENOTDIR),
+                (false, true) => return Err(EISDIR),
+                (true, true) if !Self::is_empty_dir(&target)? => return Err(ENOTEMPTY),
+                _ => {}
+            }
+
+            Self::remove_entry(new_dir, new_idx)?;
+            Self::drop_link(&target);
+
+            if target_is_dir {
+                new_dir.set_nlink(new_dir.nlink().saturating_sub(1));
+            }
+        }
+
+        if old_dir.ino() == new_dir.ino() {
+            Self::update_entries(old_dir, |entries| entries[old_idx].set_filename(new_name))?;
+        } else {
+            Self::add_entry(new_dir, new_name, inode.ino())?;
+            Self::remove_entry(old_dir, old_idx)?;
+
+            if is_dir {
+                old_dir.set_nlink(old_dir.nlink().saturating_sub(1));
+                new_dir.set_nlink(new_dir.nlink() + 1);
+            }
+        }
+
+        inode.set_ctime(inode.current_time());
+        inode.mark_dirty();
+        old_dir.mark_dirty();
+        new_dir.mark_dirty();
+
+        Ok(())
+    }
+
+    fn link(
+        old: &INode<Self::FileSystem>,
+        dir: &Locked<&INode<Self::FileSystem>, kernel::inode::WriteSem>,
+        name: &[u8],
+    ) -> Result {
+        if uapi::s_isdir(old.mode()) {
+            return Err(EPERM);
+        }
+
+        generic_permission(dir, MAY_WRITE | MAY_EXEC)?;
+        Self::check_name(name)?;
+
+        if Self::find_entry(dir, name)?.is_some() {
+            return Err(EEXIST);
+        }
+
+        Self::add_entry(dir, name, old.ino())?;
+
+        old.set_nlink(old.nlink() + 1);
+        old.set_ctime(old.current_time());
+        old.mark_dirty();
+
+        Ok(())
+    }
+
+    fn setattr(
+        inode: &Locked<&INode<Self::FileSystem>, kernel::inode::WriteSem>,
+        attr: &kernel::inode::Attr,
+    ) -> Result {
+        if let Some(size) = attr.size {
+            if size > inode.blocks() * EZFS_BLOCK_SIZE as u64 {
+                return Err(EFBIG);
+            }
+
+            let old_size = inode.size();
+            if size < old_size {
+                // Zero the truncated tail so that growing the file again exposes zeroes.
+                let h = inode.super_block().data();
+                let start = Self::block_offset(inode.data().data_blk_num())? + size as Offset;
+                let mut pos = start;
+
+                while pos < start + (old_size - size) as Offset {
+                    let mut mapped = h.mapper.mapped_folio_mut(pos)?;
+                    let len = mapped
+                        .len()
+                        .min((start + (old_size - size) as Offset - pos) as usize);
+                    mapped[..len].fill(0);
+                    mapped.flush()?;
+                    pos += len as Offset;
+                }
+
+                address_space::truncate_pagecache(inode, size);
+            }
+
+            inode.set_size(size);
+            inode.set_mtime(inode.current_time());
+        }
+
+        if let Some(mode) = attr.mode {
+            inode.set_mode(uapi::with_perm(inode.mode(), mode));
+        }
+
+        if attr.uid.is_some() || attr.gid.is_some() {
+            inode.set_owner(
+                attr.uid.unwrap_or(inode.uid()),
+                attr.gid.unwrap_or(inode.gid()),
+            );
+        }
+
+        if let Some(atime) = attr.atime {
+            inode.set_atime(atime);
+        }
+
+        if let Some(mtime) = attr.mtime {
+            inode.set_mtime(mtime);
+        }
+
+        inode.set_ctime(inode.current_time());
+        inode.mark_dirty();
+
+        Ok(())
+    }
+
+    fn getattr(inode: &INode<Self::FileSystem>) -> Result<kernel::inode::Stat> {
+        Ok(kernel::inode::generic_fillattr(inode))
+    }
+}
+
+impl file::Operations for RustEzFs {
+    type FileSystem = Self;
+
+    fn seek(file: &File<Self>, offset: Offset, whence: file::Whence) -> Result<Offset> {
+        file::generic_seek(file, offset, whence)
+    }
+
+    fn read(_: &File<Self>, _: &mut user::Writer<'_>, _: &mut Offset) -> Result<usize> {
+        Err(EISDIR)
+    }
+
+    fn read_dir(
+        file: &File<Self>,
+        inode: &Locked<&INode<Self>, kernel::inode::ReadSem>,
+        emitter: &mut file::DirEmitter,
+    ) -> Result {
+        Self::accessed(inode);
+
+        if emitter.pos() < 2 && !emitter.emit_dots(file) {
+            return Ok(());
+        }
+
+        let pos: usize = emitter.pos().try_into()?;
+
+        let sb = inode.super_block();
+        let h = sb.data();
+
+        let index = {
+            let disk_pos = pos.checked_sub(2).ok_or(EINVAL)?;
+
+            if disk_pos % size_of::<EzfsDirEntry>() != 0 {
+                return Err(EINVAL);
+            }
+
+            disk_pos / size_of::<EzfsDirEntry>()
+        };
+
+        if index >= EZFS_MAX_CHILDREN {
+            return Ok(());
+        }
+
+        let ezfs_dir_inode = inode.data();
+
+        let mapped = h
+            .mapper
+            .mapped_folio(Self::block_offset(ezfs_dir_inode.data_blk_num())?)?;
+        let bytes = mapped.get(..size_of::<DirEntryStore>()).ok_or(EIO)?;
+        let dir_entries = DirEntryStore::from_bytes(bytes).ok_or(EIO)?;
+
+        let bh = sb_bread(
+            inode.super_block(),
+            EZFS_INODE_STORE_DATABLOCK_NUMBER as u64,
+        )?;
+        let inode_store_data = bh.data();
+        let bytes = inode_store_data.get(..size_of::<InodeStore>()).ok_or(EIO)?;
+        let inode_store = InodeStore::from_bytes(bytes).ok_or(EIO)?;
+
+        let active_entries = dir_entries
+            .iter()
+            .enumerate()
+            .skip(index)
+            .filter(|(_, entry)| entry.is_active());
+
+        for (idx, entry) in active_entries {
+            let entry_inode = usize::try_from(entry.inode_no())
+                .ok()
+                .and_then(|ino| ino.checked_sub(EZFS_ROOT_INODE_NUMBER))
+                .and_then(|i| inode_store.get(i))
+                .ok_or_else(|| Self::corrupted(sb))?;
+
+            // Skipped inactive entries still count towards the position.
+            let next = (2 + (idx + 1) * size_of::<EzfsDirEntry>()) as Offset;
+
+            if !emitter.emit(
+                next - emitter.pos(),
+                entry.filename(),
+                entry.inode_no(),
+                file::DirEntryType::from_mode(entry_inode.mode()),
+            ) {
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
 }
 
-// impl kernel::inode::Operations for RustEzFs {
-//     type FileSystem = Self;
-//
-//     fn lookup(
-//         parent: &Locked<&INode<Self::FileSystem>, kernel::inode::ReadSem>,
-//         dentry: dentry::Unhashed<'_, Self::FileSystem>,
-//     ) -> Result<Option<ARef<dentry::DEntry<Self::FileSystem>>>> {
-//         let sb = &*parent.super_block();
-//         let h = sb.data();
-//
-//         let name = dentry.name();
-//
-//         if name.len() > EZFS_FILENAME_BUF_SIZE {
-//             return Err(ENAMETOOLONG);
-//         }
-//
-//         let ezfs_dir_inode = parent.data();
-//
-//         let offset = ezfs_dir_inode
-//             .data_blk_num()
-//             .checked_mul(EZFS_BLOCK_SIZE as u64)
-//             .ok_or(EIO)?;
-//
-//         let mapped = h.mapper.mapped_folio(offset.try_into()?)?;
-//         let dir_entries =
-//             DirEntryStore::from_bytes(&mapped[..size_of::<DirEntryStore>()]).ok_or(EIO)?;
-//
-//         let dir_entry = dir_entries
-//             .iter()
-//             .find(|x| x.filename() == name && x.is_active());
-//
-//         let inode = if let Some(entry) = dir_entry {
-//             Some(Self::iget(sb, entry.inode_no().try_into()?)?)
-//         } else {
-//             None
-//         };
-//
-//         dentry.splice_alias(inode)
-//     }
-// }
-
-// impl file::Operations for RustEzFs {
-//     type FileSystem = Self;
-//
-//     fn seek(file: &File<Self>, offset: Offset, whence: file::Whence) -> Result<Offset> {
-//         file::generic_seek(file, offset, whence)
-//     }
-//
-//     fn read(_: &File<Self>, _: &mut kernel::user::Writer, _: &mut Offset) -> Result<usize> {
-//         Err(EISDIR)
-//     }
-//
-//     fn read_dir(
-//         file: &File<Self>,
-//         inode: &Locked<&INode<Self>, kernel::inode::ReadSem>,
-//         emitter: &mut file::DirEmitter,
-//     ) -> Result {
-//         let pos: usize = emitter.pos().try_into().map_err(|_| ENOENT)?;
-//
-//         if pos < 2 {
-//             if !emitter.emit_dots(file) {
-//                 return Ok(());
-//             }
-//         }
-//
-//         let sb = &*inode.super_block();
-//         let h = sb.data();
-//
-//         let index = {
-//             let disk_pos = pos.checked_sub(2).ok_or(ENOENT)?;
-//
-//             if disk_pos % size_of::<EzfsDirEntry>() != 0 {
-//                 return Err(ENOENT);
-//             }
-//
-//             disk_pos / size_of::<EzfsDirEntry>()
-//         };
-//
-//         if index >= EZFS_MAX_CHILDREN {
-//             return Ok(());
-//         }
-//
-//         let ezfs_dir_inode = inode.data();
-//
-//         let offset = ezfs_dir_inode
-//             .data_blk_num()
-//             .checked_mul(EZFS_BLOCK_SIZE as u64)
-//             .ok_or(EIO)?;
-//
-//         let mapped = h.mapper.mapped_folio(offset.try_into()?)?;
-//         let dir_entries =
-//             DirEntryStore::from_bytes(&mapped[..size_of::<DirEntryStore>()]).ok_or(EIO)?;
-//
-//         let inode_store_offset = EZFS_INODE_STORE_DATABLOCK_NUMBER * EZFS_BLOCK_SIZE;
-//         let mapped_inode_store = h.mapper.mapped_folio(inode_store_offset.try_into()?)?;
-//         let inode_store =
-//             InodeStore::from_bytes(&mapped_inode_store[..size_of::<InodeStore>()]).ok_or(EIO)?;
-//
-//         let active_entries = dir_entries
-//             .iter()
-//             .skip(index)
-//             .filter(|&entry| entry.is_active());
-//
-//         for entry in active_entries {
-//             let ino: usize = entry.inode_no().try_into()?;
-//             let entry_inode = inode_store[ino - EZFS_ROOT_INODE_NUMBER];
-//             let etype = file::DirEntryType::from_mode(entry_inode.mode());
-//
-//             if !emitter.emit(
-//                 size_of::<EzfsDirEntry>() as i64,
-//                 entry.filename(),
-//                 entry.inode_no(),
-//                 etype,
-//             ) {
-//                 return Ok(());
-//             }
-//         }
-//
-//         Ok(())
-//     }
-// }
+/// File operations of regular files.
+struct EzfsFile;
+
+impl file::Operations for EzfsFile {
+    type FileSystem = RustEzFs;
+
+    fn seek(file: &File<RustEzFs>, offset: Offset, whence: file::Whence) -> Result<Offset> {
+        file::generic_seek(file, offset, whence)
+    }
+
+    fn read(
+        file: &File<RustEzFs>,
+        writer: &mut user::Writer<'_>,
+        offset: &mut Offset,
+    ) -> Result<usize> {
+        let read = address_space::generic_file_read(file, writer, offset)?;
+
+        RustEzFs::accessed(file.inode());
+        Ok(read)
+    }
+
+    fn write(
+        file: &File<RustEzFs>,
+        reader: &mut user::Reader<'_>,
+        offset: &mut Offset,
+    ) -> Result<usize> {
+        let inode = file.inode();
+
+        if reader.is_empty() {
+            return Ok(0);
+        }
+
+        let written = address_space::generic_perform_write(file, reader, offset)?;
+
+        RustEzFs::touch(inode);
+        inode.mark_dirty();
+
+        Ok(written)
+    }
+}
+
+/// Pages of a regular file map one to one onto its contiguous run of data blocks.
+impl address_space::Operations for EzfsFile {
+    type FileSystem = RustEzFs;
+
+    fn read_folio(inode: &INode<RustEzFs>, folio: &Folio, data: &mut [u8; PAGE_SIZE]) -> Result {
+        if folio.index() >= inode.blocks() {
+            data.fill(0);
+            return Ok(());
+        }
+
+        let h = inode.super_block().data();
+        let mapped = h.mapper.mapped_folio(RustEzFs::block_offset(
+            inode.data().data_blk_num() + folio.index(),
+        )?)?;
+
+        data.copy_from_slice(mapped.get(..PAGE_SIZE).ok_or(EIO)?);
+        Ok(())
+    }
+
+    fn write_folio(inode: &INode<RustEzFs>, folio: &Folio, data: &[u8; PAGE_SIZE]) -> Result {
+        if folio.index() >= inode.blocks() {
+            return Err(RustEzFs::corrupted(inode.super_block()));
+        }
+
+        let h = inode.super_block().data();
+        let mut mapped = h.mapper.mapped_folio_mut(RustEzFs::block_offset(
+            inode.data().data_blk_num() + folio.index(),
+        )?)?;
+
+        mapped
+            .get_mut(..PAGE_SIZE)
+            .ok_or(EIO)?
+            .copy_from_slice(data);
+        mapped.flush()
+    }
+
+    /// Allocates the blocks up to the end of the write before handing out the page.
+    fn write_begin(file: &File<RustEzFs>, pos: Offset, len: usize) -> Result<ARef<Folio>> {
+        let end = u64::try_from(pos)?.checked_add(len as u64).ok_or(EFBIG)?;
+
+        RustEzFs::grow(file.inode(), end.div_ceil(EZFS_BLOCK_SIZE as u64))?;
+        address_space::simple_write_begin(file, pos, len)
+    }
+}
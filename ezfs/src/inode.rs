@@ -1,49 +1,82 @@
 use crate::defs::*;
-use core::ops::Deref;
-// use kernel::time::Timespec;
-// use kernel::transmute::FromBytes;
+use core::mem::size_of;
+use core::ops::{Deref, DerefMut};
+use kernel::time::Timespec;
+use kernel::transmute::{AsBytes, FromBytes};
 use kernel::types::Result;
-// use kernel::uapi::{gid_t, mode_t, uid_t};
+use kernel::uapi::{gid_t, uid_t, umode_t};
 
 #[repr(C)]
 #[derive(Copy, Clone)]
-pub(crate) struct EzfsInode {
-    mode: u16,
-    uid: u32,
-    gid: u32,
+#[cfg_attr(kani, derive(kani::Arbitrary))]
+pub struct EzfsInode {
+    mode: umode_t,
+    _pad0: u16,
+    uid: uid_t,
+    gid: gid_t,
+    _pad1: u32,
     i_atime: i64, /* access time */
     i_mtime: i64, /* modified time */
     i_ctime: i64, /* change time */
     nlink: u32,
+    _pad2: u32,
     data_blk_num: u64,
     file_size: u64,
     nblocks: u64,
 }
 
+// The padding is spelled out so that every byte of an inode is initialized.
+const _: () = assert!(size_of::<EzfsInode>() == 72);
+
+// SAFETY: EzfsInode only contains integers, for which any bit pattern is valid
+unsafe impl FromBytes for EzfsInode {}
+
+// SAFETY: EzfsInode only contains integers and has no implicit padding
+unsafe impl AsBytes for EzfsInode {}
+
 impl EzfsInode {
-    pub(crate) fn mode(&self) -> u16 {
+    /// A new inode owning the single data block `data_blk_num`.
+    pub(crate) fn new(mode: umode_t, uid: uid_t, gid: gid_t, data_blk_num: u64) -> Self {
+        Self {
+            mode,
+            _pad0: 0,
+            uid,
+            gid,
+            _pad1: 0,
+            i_atime: 0,
+            i_mtime: 0,
+            i_ctime: 0,
+            nlink: 1,
+            _pad2: 0,
+            data_blk_num,
+            file_size: 0,
+            nblocks: 1,
+        }
+    }
+
+    pub(crate) fn mode(&self) -> umode_t {
         self.mode
     }
 
-    pub(crate) fn uid(&self) -> u32 {
+    pub(crate) fn uid(&self) -> uid_t {
         self.uid
     }
 
-    pub(crate) fn gid(&self) -> u32 {
+    pub(crate) fn gid(&self) -> gid_t {
         self.gid
     }
 
-    // pub(crate) fn atime(&self) -> Result<Timespec> {
-    //     Timespec::new(self.i_atime.try_into()?, 0)
-    // }
-    //
-    // pub(crate) fn mtime(&self) -> Result<Timespec> {
-    //     Timespec::new(self.i_mtime.try_into()?, 0)
-    // }
-    //
-    // pub(crate) fn ctime(&self) -> Result<Timespec> {
-    //     Timespec::new(self.i_ctime.try_into()?, 0)
-    // }
+    pub(crate) fn atime(&self) -> Result<Timespec> {
+        Timespec::new(self.i_atime.try_into()?, 0)
+    }
+
+    pub(crate) fn mtime(&self) -> Result<Timespec> {
+        Timespec::new(self.i_mtime.try_into()?, 0)
+    }
+
+    pub(crate) fn ctime(&self) -> Result<Timespec> {
+        Timespec::new(self.i_ctime.try_into()?, 0)
+    }
 
     pub(crate) fn nlink(&self) -> u32 {
         self.nlink
@@ -60,6 +93,34 @@ impl EzfsInode {
     pub(crate) fn nblocks(&self) -> u64 {
         self.nblocks
     }
+
+    pub(crate) fn set_mode(&mut self, mode: umode_t) {
+        self.mode = mode;
+    }
+
+    pub(crate) fn set_owner(&mut self, uid: uid_t, gid: gid_t) {
+        self.uid = uid;
+        self.gid = gid;
+    }
+
+    pub(crate) fn set_nlink(&mut self, nlink: u32) {
+        self.nlink = nlink;
+    }
+
+    pub(crate) fn set_file_size(&mut self, file_size: u64) {
+        self.file_size = file_size;
+    }
+
+    pub(crate) fn set_nblocks(&mut self, nblocks: u64) {
+        self.nblocks = nblocks;
+    }
+
+    /// Stores the given times; ezfs keeps whole seconds only.
+    pub(crate) fn set_times(&mut self, atime: Timespec, mtime: Timespec, ctime: Timespec) {
+        self.i_atime = atime.sec();
+        self.i_mtime = mtime.sec();
+        self.i_ctime = ctime.sec();
+    }
 }
 
 #[repr(C)]
@@ -75,5 +136,14 @@ impl Deref for InodeStore {
     }
 }
 
+impl DerefMut for InodeStore {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inodes
+    }
+}
+
 // SAFETY: EzfsInode is FromBytes, so array of them is too
-// unsafe impl FromBytes for InodeStore {}
+unsafe impl FromBytes for InodeStore {}
+
+// SAFETY: EzfsInode is AsBytes, so array of them is too
+unsafe impl AsBytes for InodeStore {}
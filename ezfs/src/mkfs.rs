@@ -0,0 +1,49 @@
+use crate::defs::*;
+use crate::inode::EzfsInode;
+use crate::sb::EzfsSuperblockDisk;
+use kernel::block::BlockDevice;
+use kernel::time;
+use kernel::transmute::AsBytes;
+use kernel::types::Result;
+use kernel::types::code::{EIO, ENOSPC};
+use kernel::uapi::S_IFDIR;
+
+/// Formats `device` as an empty RustEzFs image containing only the root directory.
+pub fn format(device: &dyn BlockDevice) -> Result {
+    let disk_blocks = device.size() / EZFS_BLOCK_SIZE as u64;
+
+    if disk_blocks <= EZFS_ROOT_DATABLOCK_NUMBER as u64 {
+        return Err(ENOSPC);
+    }
+
+    // Writes `buf` at the start of block `blk`, failing with `EIO` on a short write.
+    let write = |buf: &[u8], blk: usize| {
+        if device.write_at(buf, (blk * EZFS_BLOCK_SIZE) as u64)? != buf.len() {
+            return Err(EIO);
+        }
+
+        Ok(())
+    };
+    let zero = [0u8; EZFS_BLOCK_SIZE];
+
+    for blk in EZFS_INODE_STORE_DATABLOCK_NUMBER..=EZFS_ROOT_DATABLOCK_NUMBER {
+        write(&zero, blk)?;
+    }
+
+    let mut root = EzfsInode::new(
+        (S_IFDIR | 0o755) as u16,
+        0,
+        0,
+        EZFS_ROOT_DATABLOCK_NUMBER as u64,
+    );
+    root.set_nlink(2);
+    root.set_file_size(EZFS_BLOCK_SIZE as u64);
+    let now = time::current_time();
+    root.set_times(now, now, now);
+    write(root.as_bytes(), EZFS_INODE_STORE_DATABLOCK_NUMBER)?;
+
+    let disk_sb = EzfsSuperblockDisk::new(disk_blocks);
+    write(disk_sb.as_bytes(), EZFS_SUPERBLOCK_DATABLOCK_NUMBER)?;
+
+    device.flush()
+}
@@ -0,0 +1,84 @@
+use core::fmt::Write;
+use kernel::fs::{Context, ParamSpec, ParamValue};
+use kernel::types::Result;
+use kernel::types::code::EINVAL;
+use kernel::uapi::{gid_t, uid_t};
+
+const OPT_UID: u32 = 0;
+const OPT_GID: u32 = 1;
+const OPT_ERRORS: u32 = 2;
+
+const ERRORS: &[(&str, u32)] = &[
+    ("continue", Errors::Continue as u32),
+    ("remount-ro", Errors::RemountRo as u32),
+    ("panic", Errors::Panic as u32),
+];
+
+pub(crate) const PARAMETERS: &[ParamSpec] = &[
+    ParamSpec::u32("uid", OPT_UID),
+    ParamSpec::u32("gid", OPT_GID),
+    ParamSpec::enumeration("errors", OPT_ERRORS, ERRORS),
+];
+
+/// What to do on finding the disk corrupted, chosen with `errors=`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum Errors {
+    #[default]
+    Continue = 0,
+    RemountRo = 1,
+    Panic = 2,
+}
+
+/// Mount options of an ezfs superblock.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct EzfsMountOpts {
+    /// Owner of the inodes created on this mount, instead of the creator.
+    pub uid: Option<uid_t>,
+    pub gid: Option<gid_t>,
+    pub errors: Errors,
+}
+
+impl EzfsMountOpts {
+    /// Applies the options in `ctx` on top of `self`.
+    ///
+    /// Fails with `EINVAL` for ids of -1, which do not name an owner.
+    pub(crate) fn apply(mut self, ctx: &Context) -> Result<Self> {
+        for param in ctx.params() {
+            match (param.opt, &param.value) {
+                (OPT_UID, &ParamValue::U32(uid)) if uid != uid_t::MAX => self.uid = Some(uid),
+                (OPT_GID, &ParamValue::U32(gid)) if gid != gid_t::MAX => self.gid = Some(gid),
+                (OPT_ERRORS, &ParamValue::Enum(errors)) => {
+                    self.errors = match errors {
+                        e if e == Errors::RemountRo as u32 => Errors::RemountRo,
+                        e if e == Errors::Panic as u32 => Errors::Panic,
+                        _ => Errors::Continue,
+                    }
+                }
+                _ => return Err(EINVAL),
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Appends the options that differ from the defaults, as `show_options` does.
+    pub(crate) fn show(&self, out: &mut String) -> Result {
+        let default = Self::default();
+
+        if let Some(uid) = self.uid {
+            write!(out, ",uid={uid}").map_err(|_| EINVAL)?;
+        }
+
+        if let Some(gid) = self.gid {
+            write!(out, ",gid={gid}").map_err(|_| EINVAL)?;
+        }
+
+        if self.errors != default.errors
+            && let Some((name, _)) = ERRORS.iter().find(|(_, e)| *e == self.errors as u32)
+        {
+            write!(out, ",errors={name}").map_err(|_| EINVAL)?;
+        }
+
+        Ok(())
+    }
+}
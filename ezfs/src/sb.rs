@@ -1,14 +1,14 @@
 use crate::RustEzFs;
-use crate::defs::{EZFS_BLOCK_SIZE, EZFS_MAX_DATA_BLKS, EZFS_MAX_INODES};
-use crate::inode::InodeStore;
+use crate::defs::{EZFS_BLOCK_SIZE, EZFS_MAGIC_NUMBER, EZFS_MAX_DATA_BLKS, EZFS_MAX_INODES};
+use crate::options::EzfsMountOpts;
 use core::mem::size_of;
-use kernel::fs::FileSystem;
 use kernel::inode;
-use kernel::types::{Error, Result};
+use kernel::types::Result;
 // use kernel::new_mutex;
 // use kernel::prelude::*;
 // use kernel::sync::Mutex;
-// use kernel::transmute::FromBytes;
+use kernel::transmute::{AsBytes, FromBytes};
+use kernel::types::code::EINVAL;
 use std::ops::{Deref, DerefMut};
 use std::sync::Mutex;
 
@@ -22,14 +22,43 @@ pub(crate) struct EzfsSuperblockDiskRaw {
     zero_data_blocks: [u32; (EZFS_MAX_DATA_BLKS / 32) + 1],
 }
 
-// TODO: assert size is equal to 4096 bytes
+const _: () = assert!(size_of::<EzfsSuperblockDiskRaw>() == 48);
+
+// SAFETY: EzfsSuperblockDiskRaw only contains integers, for which any bit pattern is valid
+unsafe impl FromBytes for EzfsSuperblockDiskRaw {}
+
+// SAFETY: EzfsSuperblockDiskRaw only contains integers and its fields leave no padding
+unsafe impl AsBytes for EzfsSuperblockDiskRaw {}
+
 #[repr(C)]
 pub(crate) struct EzfsSuperblockDisk {
     data: EzfsSuperblockDiskRaw,
     _padding: [u8; EZFS_BLOCK_SIZE - size_of::<EzfsSuperblockDiskRaw>()],
 }
 
+const _: () = assert!(size_of::<EzfsSuperblockDisk>() == EZFS_BLOCK_SIZE);
+
+// SAFETY: Both fields are FromBytes
+unsafe impl FromBytes for EzfsSuperblockDisk {}
+
+// SAFETY: Both fields are AsBytes, and the size assertion above rules out trailing padding
+unsafe impl AsBytes for EzfsSuperblockDisk {}
+
 impl EzfsSuperblockDisk {
+    /// A superblock for a freshly formatted disk of `disk_blocks` blocks, on which only the root
+    /// inode and its directory block are allocated.
+    pub(crate) fn new(disk_blocks: u64) -> Self {
+        let mut disk_sb = Self::default();
+
+        disk_sb.data.version = 1;
+        disk_sb.data.magic = EZFS_MAGIC_NUMBER as u64;
+        disk_sb.data.disk_blocks = disk_blocks;
+        disk_sb.data.free_inodes[0] = 1;
+        disk_sb.data.free_data_blocks[0] = 1;
+
+        disk_sb
+    }
+
     pub fn magic(&self) -> u64 {
         self.data.magic
     }
@@ -57,11 +86,13 @@ impl Default for EzfsSuperblockDisk {
     }
 }
 
-pub(crate) struct EzfsSuperblock {
+pub struct EzfsSuperblock {
     pub(crate) version: u64,
     pub(crate) magic: u64,
     pub(crate) disk_blocks: u64,
     pub(crate) data: Mutex<EzfsSuperblockData>,
+    /// Set at mount time and replaced by remounting.
+    pub(crate) opts: Mutex<EzfsMountOpts>,
     pub(crate) mapper: inode::Mapper<RustEzFs>,
 }
 
@@ -72,7 +103,11 @@ pub(crate) struct EzfsSuperblockData {
 }
 
 impl EzfsSuperblock {
-    pub(crate) fn new(disk_sb: EzfsSuperblockDisk, mapper: inode::Mapper<RustEzFs>) -> Self {
+    pub(crate) fn new(
+        disk_sb: &EzfsSuperblockDisk,
+        opts: EzfsMountOpts,
+        mapper: inode::Mapper<RustEzFs>,
+    ) -> Self {
         Self {
             version: disk_sb.data.version,
             magic: disk_sb.data.magic,
@@ -82,6 +117,7 @@ impl EzfsSuperblock {
                 free_data_blocks: Bitmap::new(disk_sb.data.free_data_blocks),
                 zero_data_blocks: Bitmap::new(disk_sb.data.zero_data_blocks),
             }),
+            opts: Mutex::new(opts),
             mapper,
         }
     }
@@ -89,6 +125,21 @@ impl EzfsSuperblock {
     pub(crate) fn magic(&self) -> u64 {
         self.magic
     }
+
+    /// The on-disk form of the superblock, with the current allocation bitmaps.
+    pub(crate) fn to_disk(&self) -> Result<EzfsSuperblockDisk> {
+        let sb_data = self.data.lock()?;
+        let mut disk_sb = EzfsSuperblockDisk::default();
+
+        disk_sb.data.version = self.version;
+        disk_sb.data.magic = self.magic;
+        disk_sb.data.disk_blocks = self.disk_blocks;
+        disk_sb.data.free_inodes = *sb_data.free_inodes;
+        disk_sb.data.free_data_blocks = *sb_data.free_data_blocks;
+        disk_sb.data.zero_data_blocks = *sb_data.zero_data_blocks;
+
+        Ok(disk_sb)
+    }
 }
 
 #[repr(transparent)]
@@ -114,7 +165,7 @@ impl<const N: usize> Bitmap<N> {
     pub fn set_bit(&mut self, block_num: u64) -> Result {
         let idx = (block_num / 32) as usize;
         let mask = 1 << (block_num % 32);
-        let val = self.inner.get_mut(idx).ok_or(Error(20))?;
+        let val = self.inner.get_mut(idx).ok_or(EINVAL)?;
         *val |= mask;
 
         Ok(())
@@ -124,7 +175,7 @@ impl<const N: usize> Bitmap<N> {
     pub fn clear_bit(&mut self, block_num: u64) -> Result {
         let idx: usize = (block_num / 32) as usize;
         let mask = 1 << (block_num % 32);
-        let val = self.inner.get_mut(idx).ok_or(Error(20))?;
+        let val = self.inner.get_mut(idx).ok_or(EINVAL)?;
         *val &= !mask;
 
         Ok(())
@@ -0,0 +1,881 @@
+use crate::{RustEzFs, format};
+use kernel::block::{BlockDevice, CrashDevice, FaultyDevice, MemDevice};
+use kernel::cred::{self, Credentials};
+use kernel::dentry::{self, DEntry};
+use kernel::file::{DirEmitter, DirEntryType, File};
+use kernel::fs::{self, Offset};
+use kernel::inode::Attr;
+use kernel::sb::SuperBlock;
+use kernel::time::{self, Timespec};
+use kernel::types::ARef;
+use kernel::types::code::{
+    EACCES, EEXIST, EFAULT, EINVAL, EIO, EISDIR, ENAMETOOLONG, ENOENT, ENOSPC, ENOTEMPTY, EPERM,
+    EROFS, EUCLEAN,
+};
+use kernel::uapi::{
+    O_APPEND, O_CREAT, O_EXCL, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY, S_IFDIR, S_IFREG,
+};
+use kernel::user;
+use kernel::vfs::Vfs;
+use kernel::writeback;
+use std::sync::Arc;
+
+const DISK_BLOCKS: usize = 16;
+
+fn device() -> Arc<MemDevice> {
+    let device = Arc::new(MemDevice::new(DISK_BLOCKS * 4096));
+    format(&*device).unwrap();
+    device
+}
+
+fn mount(device: &Arc<MemDevice>) -> SuperBlock<RustEzFs> {
+    fs::mount::<RustEzFs>(Some(device.clone() as Arc<dyn BlockDevice>), "").unwrap()
+}
+
+/// A formatted device wrapped to inject faults, with blocks numbered as ezfs does.
+fn faulty_device() -> Arc<FaultyDevice> {
+    Arc::new(FaultyDevice::new(device(), 4096))
+}
+
+fn create(dir: &ARef<DEntry<RustEzFs>>, name: &[u8]) -> File<RustEzFs> {
+    let inode = dir.inode().unwrap();
+    let locked = inode.lock();
+    let child = inode.ops().create(&locked, name, S_IFREG | 0o644).unwrap();
+
+    File::new(child)
+}
+
+fn entries(dir: &ARef<DEntry<RustEzFs>>) -> Vec<(Vec<u8>, DirEntryType)> {
+    let file = File::open(dir.clone()).unwrap();
+    let inode = dir.inode().unwrap();
+    let mut emitter = DirEmitter::new(0, 4096);
+
+    inode
+        .fops()
+        .read_dir(&file, &inode.lock_shared(), &mut emitter)
+        .unwrap();
+
+    emitter
+        .into_entries()
+        .into_iter()
+        .map(|e| (e.name, e.etype))
+        .collect()
+}
+
+#[test]
+fn mount_rejects_unformatted_device() {
+    let device: Arc<dyn BlockDevice> = Arc::new(MemDevice::new(DISK_BLOCKS * 4096));
+
+    assert!(fs::mount::<RustEzFs>(Some(device), "").is_err());
+    assert!(fs::mount::<RustEzFs>(None, "").is_err());
+}
+
+#[test]
+fn create_write_and_look_up() {
+    let device = device();
+    let sb = mount(&device);
+    let root = sb.root().unwrap();
+    assert_eq!(root.inode().unwrap().mode(), S_IFDIR | 0o755);
+
+    let file = create(&root, b"hello");
+    let mut pos = 0;
+    let written = file
+        .inode()
+        .fops()
+        .write(&file, &mut user::Reader::new(b"hello, world"), &mut pos)
+        .unwrap();
+    assert_eq!(written, 12);
+
+    let inode = root.inode().unwrap();
+    inode
+        .ops()
+        .mkdir(&inode.lock(), b"dir", S_IFDIR | 0o755)
+        .unwrap();
+    drop((file, inode));
+
+    assert_eq!(
+        entries(&root),
+        [
+            (b".".to_vec(), DirEntryType::Dir),
+            (b"..".to_vec(), DirEntryType::Dir),
+            (b"hello".to_vec(), DirEntryType::Reg),
+            (b"dir".to_vec(), DirEntryType::Dir),
+        ]
+    );
+
+    let file = File::open(dentry::walk(&root, b"hello").unwrap()).unwrap();
+    let mut buf = [0u8; 32];
+    let mut pos = 0;
+    let read = file
+        .inode()
+        .fops()
+        .read(&file, &mut user::Writer::new(&mut buf), &mut pos)
+        .unwrap();
+    assert_eq!(&buf[..read], b"hello, world");
+
+    let dir = dentry::walk(&root, b"dir").unwrap();
+    assert_eq!(dir.inode().unwrap().nlink(), 2);
+    assert!(dentry::walk(&root, b"missing").unwrap().is_negative());
+
+    drop((file, dir, root));
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn unlink_frees_the_inode() {
+    let device = device();
+    let sb = mount(&device);
+    let root = sb.root().unwrap();
+
+    let ino = create(&root, b"a").inode().ino();
+    let inode = root.inode().unwrap();
+    inode.ops().unlink(&inode.lock(), b"a").unwrap();
+
+    assert_eq!(entries(&root).len(), 2);
+    assert_eq!(create(&root, b"b").inode().ino(), ino);
+
+    drop((inode, root));
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn unlinked_inodes_are_freed_on_eviction() {
+    let device = device();
+    let sb = mount(&device);
+    let root = sb.root().unwrap();
+    let empty = fs::statfs(&sb).unwrap();
+    assert_eq!((empty.blocks, empty.bfree), (14, 13));
+    assert_eq!(empty.ffree, empty.files - 1);
+
+    let file = create(&root, b"a");
+    let inode = root.inode().unwrap();
+    inode.ops().unlink(&inode.lock(), b"a").unwrap();
+
+    // The open file keeps its inode and block until it is closed.
+    let stat = fs::statfs(&sb).unwrap();
+    assert_eq!((stat.bfree, stat.ffree), (empty.bfree - 1, empty.ffree - 1));
+
+    drop(file);
+    assert_eq!(fs::statfs(&sb).unwrap(), empty);
+
+    drop((inode, root));
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn operations_update_times() {
+    let at = |sec| Timespec::new(sec, 0).unwrap();
+
+    let device = device();
+    let sb = mount(&device);
+    let root = sb.root().unwrap();
+    let dir = root.inode().unwrap();
+
+    time::set_clock(Some(at(100)));
+    let file = create(&root, b"f");
+    let inode = file.inode();
+    assert_eq!(
+        (inode.atime(), inode.mtime(), inode.ctime()),
+        (at(100), at(100), at(100))
+    );
+    assert_eq!((dir.mtime(), dir.ctime()), (at(100), at(100)));
+
+    time::set_clock(Some(at(200)));
+    let mut pos = 0;
+    inode
+        .fops()
+        .write(&file, &mut user::Reader::new(b"data"), &mut pos)
+        .unwrap();
+    assert_eq!(
+        (inode.atime(), inode.mtime(), inode.ctime()),
+        (at(100), at(200), at(200))
+    );
+
+    time::set_clock(Some(at(300)));
+    let mut buf = [0u8; 4];
+    let mut pos = 0;
+    inode
+        .fops()
+        .read(&file, &mut user::Writer::new(&mut buf), &mut pos)
+        .unwrap();
+    assert_eq!((inode.atime(), inode.mtime()), (at(300), at(200)));
+    assert_eq!(dir.mtime(), at(100));
+
+    time::set_clock(None);
+    drop((file, dir, root));
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn changes_persist_across_remount() {
+    let device = device();
+    let sb = mount(&device);
+    let root = sb.root().unwrap();
+
+    let file = create(&root, b"a");
+    let ino = file.inode().ino();
+    let mut pos = 0;
+    file.inode()
+        .fops()
+        .write(&file, &mut user::Reader::new(b"persisted"), &mut pos)
+        .unwrap();
+    let inode = root.inode().unwrap();
+    inode
+        .ops()
+        .mkdir(&inode.lock(), b"d", S_IFDIR | 0o755)
+        .unwrap();
+
+    // Nothing reaches the inode store or the bitmaps before writeback.
+    assert!(file.inode().is_dirty());
+    assert!(sb.dirty_inodes() > 0);
+
+    drop((file, inode, root));
+    fs::kill_sb(sb).unwrap();
+
+    let sb = mount(&device);
+    let root = sb.root().unwrap();
+    assert_eq!(root.inode().unwrap().nlink(), 3);
+    assert_eq!(entries(&root).len(), 4);
+
+    let file = File::open(dentry::walk(&root, b"a").unwrap()).unwrap();
+    let mut buf = [0u8; 16];
+    let mut pos = 0;
+    let read = file
+        .inode()
+        .fops()
+        .read(&file, &mut user::Writer::new(&mut buf), &mut pos)
+        .unwrap();
+    assert_eq!(&buf[..read], b"persisted");
+
+    // The allocations were persisted, so new files get fresh inodes.
+    let other = create(&root, b"b");
+    assert!(other.inode().ino() != ino);
+    assert!(other.inode().ino() != dentry::walk(&root, b"d").unwrap().inode().unwrap().ino());
+
+    drop((file, other, root));
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn reads_are_served_from_the_page_cache() {
+    let device = faulty_device();
+    let sb = fs::mount::<RustEzFs>(Some(device.clone() as Arc<dyn BlockDevice>), "").unwrap();
+    let root = sb.root().unwrap();
+    time::set_clock(Some(Timespec::new(100, 0).unwrap()));
+
+    let data: Vec<u8> = (0..6000).map(|i| i as u8).collect();
+    let file = create(&root, b"f");
+    let mut pos = 0;
+    file.inode()
+        .fops()
+        .write(&file, &mut user::Reader::new(&data), &mut pos)
+        .unwrap();
+
+    // Evicting the inode writes its pages back, so reading it again has to go to the device.
+    drop(file);
+    let file = File::open(dentry::walk(&root, b"f").unwrap()).unwrap();
+    let read = |file: &File<RustEzFs>| {
+        let mut buf = vec![0u8; 8192];
+        let mut pos = 0;
+        let n = file
+            .inode()
+            .fops()
+            .read(file, &mut user::Writer::new(&mut buf), &mut pos)
+            .unwrap();
+        buf.truncate(n);
+        buf
+    };
+
+    let before = device.reads();
+    assert_eq!(read(&file), data);
+    let after = device.reads();
+    assert!(after > before);
+
+    assert_eq!(read(&file), data);
+    assert_eq!(device.reads(), after);
+    assert_eq!(file.inode().mapping().nr_pages(), 2);
+
+    time::set_clock(None);
+    drop((file, root));
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn mount_options_are_validated_and_applied() {
+    let device = device();
+    let mount_with =
+        |data: &str| fs::mount::<RustEzFs>(Some(device.clone() as Arc<dyn BlockDevice>), data);
+
+    assert_eq!(mount_with("bogus").err(), Some(EINVAL));
+    assert_eq!(mount_with("uid=-1").err(), Some(EINVAL));
+    assert_eq!(mount_with("uid=4294967295").err(), Some(EINVAL));
+    assert_eq!(mount_with("errors=ignore").err(), Some(EINVAL));
+
+    let sb = mount_with("uid=1000,gid=100,errors=remount-ro,noatime").unwrap();
+    assert_eq!(
+        fs::show_options(&sb).unwrap(),
+        "rw,noatime,uid=1000,gid=100,errors=remount-ro"
+    );
+
+    assert_eq!((sb.blocksize(), sb.maxbytes()), (4096, 14 * 4096));
+    assert_eq!(sb.time_gran(), 1_000_000_000);
+    assert!(!sb.is_rdonly());
+
+    let root = sb.root().unwrap();
+    let file = create(&root, b"f");
+    let inode = file.inode();
+    assert_eq!((inode.uid(), inode.gid()), (1000, 100));
+
+    time::set_clock(Some(Timespec::new(4_000_000_000, 0).unwrap()));
+    let mut pos = 0;
+    inode
+        .fops()
+        .read(&file, &mut user::Writer::new(&mut [0; 4]), &mut pos)
+        .unwrap();
+    assert_ne!(inode.atime(), Timespec::new(4_000_000_000, 0).unwrap());
+
+    fs::remount(&sb, "errors=panic").unwrap();
+    assert_eq!(
+        fs::show_options(&sb).unwrap(),
+        "rw,uid=1000,gid=100,errors=panic"
+    );
+    assert_eq!(fs::remount(&sb, "gid=x"), Err(EINVAL));
+
+    inode
+        .fops()
+        .read(&file, &mut user::Writer::new(&mut [0; 4]), &mut pos)
+        .unwrap();
+    assert_eq!(inode.atime(), Timespec::new(4_000_000_000, 0).unwrap());
+
+    time::set_clock(None);
+    drop((file, root));
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn errors_use_the_right_codes() {
+    let device = device();
+    let sb = mount(&device);
+    let root = sb.root().unwrap();
+    let dir = root.inode().unwrap();
+    let locked = dir.lock();
+    let create = |name: &[u8]| dir.ops().create(&locked, name, S_IFREG | 0o644).map(drop);
+
+    assert_eq!(create(&[b'x'; 200]), Err(ENAMETOOLONG));
+    assert_eq!(create(b"a/b"), Err(EINVAL));
+    assert_eq!(dir.ops().unlink(&locked, b"nope"), Err(ENOENT));
+
+    // The root directory takes one of the 14 data blocks.
+    for i in 0..13 {
+        create(format!("f{i}").as_bytes()).unwrap();
+    }
+    assert_eq!(create(b"full"), Err(ENOSPC));
+    assert_eq!(create(b"f0"), Err(EEXIST));
+
+    drop(locked);
+    assert_eq!(
+        dentry::lookup_one(&root, &[b'x'; 119]).err(),
+        Some(ENAMETOOLONG)
+    );
+    drop((dir, root));
+    fs::kill_sb(sb).unwrap();
+
+    device.write_at(&[0; 8], 8).unwrap();
+    assert_eq!(
+        fs::mount::<RustEzFs>(Some(device as Arc<dyn BlockDevice>), "").err(),
+        Some(EINVAL)
+    );
+}
+
+/// A device whose root directory has an entry `a` naming an inode past the end of the store.
+fn corrupt_device() -> Arc<MemDevice> {
+    let device = device();
+    let sb = mount(&device);
+    let root = sb.root().unwrap();
+    drop(create(&root, b"a"));
+    drop(root);
+    fs::kill_sb(sb).unwrap();
+
+    // Point the entry of `a` past the end of the inode store, and mark that inode allocated
+    // in the superblock's bitmap, which has bits to spare.
+    let image = device.contents();
+    let entry = image[2 * 4096..3 * 4096]
+        .chunks(128)
+        .position(|entry| entry[9..11] == *b"a\0")
+        .unwrap();
+    let ino: u64 = 58;
+    device
+        .write_at(&ino.to_ne_bytes(), (2 * 4096 + entry * 128) as u64)
+        .unwrap();
+    let word = 24 + (ino as usize - 1) / 32 * 4;
+    let bits = u32::from_ne_bytes(image[word..word + 4].try_into().unwrap());
+    device
+        .write_at(&(bits | 1 << ((ino - 1) % 32)).to_ne_bytes(), word as u64)
+        .unwrap();
+
+    device
+}
+
+#[test]
+fn corrupt_inode_numbers_are_rejected() {
+    let device = corrupt_device();
+    let sb = mount(&device);
+    let root = sb.root().unwrap();
+    assert_eq!(dentry::walk(&root, b"a").err(), Some(EUCLEAN));
+    assert!(!sb.is_rdonly());
+
+    drop(root);
+    fs::kill_sb(sb).unwrap();
+
+    let sb = fs::mount::<RustEzFs>(
+        Some(device.clone() as Arc<dyn BlockDevice>),
+        "errors=remount-ro",
+    )
+    .unwrap();
+    let root = sb.root().unwrap();
+    // Leaves the new inode and the bitmaps dirty.
+    drop(create(&root, b"b"));
+    let image = device.contents();
+    assert_eq!(dentry::walk(&root, b"a").err(), Some(EUCLEAN));
+    assert!(sb.is_rdonly());
+    assert_eq!(fs::show_options(&sb).unwrap(), "ro,errors=remount-ro");
+
+    // Nothing more is written to a disk found corrupted.
+    drop(root);
+    fs::kill_sb(sb).unwrap();
+    assert!(device.contents() == image);
+}
+
+#[test]
+#[should_panic(expected = "corrupted")]
+fn corrupt_disks_panic_with_errors_panic() {
+    let device = corrupt_device();
+    let sb = fs::mount::<RustEzFs>(Some(device as Arc<dyn BlockDevice>), "errors=panic").unwrap();
+
+    let _ = dentry::walk(&sb.root().unwrap(), b"a");
+}
+
+#[test]
+fn mount_reports_io_errors() {
+    let device = faulty_device();
+    let mount = || fs::mount::<RustEzFs>(Some(device.clone() as Arc<dyn BlockDevice>), "");
+
+    device.fail_reads(0);
+    assert_eq!(mount().err(), Some(EIO));
+
+    device.clear();
+    device.short_reads(0, 512);
+    assert_eq!(mount().err(), Some(EIO));
+
+    // The inode store is first read for the root inode.
+    device.clear();
+    device.fail_reads(1);
+    assert_eq!(mount().err(), Some(EIO));
+
+    device.clear();
+    device.corrupt(8, 0x01);
+    assert_eq!(mount().err(), Some(EINVAL));
+
+    device.clear();
+    let sb = mount().unwrap();
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn lookup_reports_io_errors() {
+    let device = faulty_device();
+    let sb = fs::mount::<RustEzFs>(Some(device.clone() as Arc<dyn BlockDevice>), "").unwrap();
+    let root = sb.root().unwrap();
+    drop(create(&root, b"a"));
+
+    // The root directory block.
+    device.fail_reads(2);
+    assert_eq!(dentry::walk(&root, b"missing").err(), Some(EIO));
+
+    device.clear();
+    device.fail_reads(1);
+    assert_eq!(dentry::walk(&root, b"a").err(), Some(EIO));
+
+    // Failed lookups are not cached.
+    device.clear();
+    assert!(dentry::walk(&root, b"missing").unwrap().is_negative());
+    assert!(!dentry::walk(&root, b"a").unwrap().is_negative());
+
+    drop(root);
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn failed_allocations_are_rolled_back() {
+    let device = faulty_device();
+    let sb = fs::mount::<RustEzFs>(Some(device.clone() as Arc<dyn BlockDevice>), "").unwrap();
+    let root = sb.root().unwrap();
+    let dir = root.inode().unwrap();
+    let empty = fs::statfs(&sb).unwrap();
+    let try_create = |name: &[u8]| {
+        dir.ops()
+            .create(&dir.lock(), name, S_IFREG | 0o644)
+            .map(drop)
+    };
+
+    // Linking the new entry into the root directory fails.
+    device.fail_writes(2);
+    assert_eq!(try_create(b"a"), Err(EIO));
+    assert_eq!(fs::statfs(&sb).unwrap(), empty);
+
+    // Zeroing the new data block fails.
+    device.clear();
+    device.fail_writes_after(0);
+    assert_eq!(try_create(b"a"), Err(EIO));
+    assert_eq!(fs::statfs(&sb).unwrap(), empty);
+
+    device.clear();
+    let file = create(&root, b"a");
+    let inode = file.inode();
+    let data = [7u8; 6000];
+    let write = || {
+        inode
+            .fops()
+            .write(&file, &mut user::Reader::new(&data), &mut 0)
+    };
+
+    // Growing the file into its second block fails after the first page was written.
+    device.fail_writes(inode.data().data_blk_num() + 1);
+    assert_eq!(write(), Ok(4096));
+    assert_eq!((inode.size(), inode.blocks()), (4096, 1));
+    assert_eq!(fs::statfs(&sb).unwrap().bfree, empty.bfree - 1);
+
+    device.clear();
+    assert_eq!(write(), Ok(6000));
+    assert_eq!(inode.blocks(), 2);
+
+    drop((file, dir, root));
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn io_errors_after_the_first_page_give_short_counts() {
+    let device = faulty_device();
+    let sb = fs::mount::<RustEzFs>(Some(device.clone() as Arc<dyn BlockDevice>), "").unwrap();
+    let root = sb.root().unwrap();
+    let file = create(&root, b"f");
+    let data: Vec<u8> = (0..6000).map(|i| i as u8).collect();
+    file.inode()
+        .fops()
+        .write(&file, &mut user::Reader::new(&data), &mut 0)
+        .unwrap();
+
+    // Evicting the inode drops its pages, so both are read from the device again.
+    drop(file);
+    let file = File::open(dentry::walk(&root, b"f").unwrap()).unwrap();
+    device.fail_reads(file.inode().data().data_blk_num() + 1);
+
+    let mut buf = [0u8; 8192];
+    let mut pos = 0;
+    let mut writer = user::Writer::new(&mut buf);
+    assert_eq!(
+        file.inode().fops().read(&file, &mut writer, &mut pos),
+        Ok(4096)
+    );
+    assert_eq!((pos, &buf[..4096]), (4096, &data[..4096]));
+    let mut writer = user::Writer::new(&mut buf);
+    assert_eq!(
+        file.inode().fops().read(&file, &mut writer, &mut pos),
+        Err(EIO)
+    );
+    assert_eq!(pos, 4096);
+
+    // The second page has to be read before it is partly overwritten.
+    let mut pos = 100;
+    let mut reader = user::Reader::new(&data);
+    assert_eq!(
+        file.inode().fops().write(&file, &mut reader, &mut pos),
+        Ok(3996)
+    );
+    assert_eq!((pos, file.inode().size()), (4096, 6000));
+    let mut reader = user::Reader::new(&data[..10]);
+    assert_eq!(
+        file.inode().fops().write(&file, &mut reader, &mut pos),
+        Err(EIO)
+    );
+    assert_eq!(pos, 4096);
+
+    device.clear();
+    drop((file, root));
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn short_copies_are_handled() {
+    let device = device();
+    let sb = mount(&device);
+    let root = sb.root().unwrap();
+    let file = create(&root, b"f");
+    let created = fs::statfs(&sb).unwrap();
+    let data: Vec<u8> = (0..6000).map(|i| i as u8).collect();
+    let write = |file: &File<RustEzFs>, pos: Offset, fault: usize| {
+        let mut reader = user::Reader::new(&data).faulting_at(fault);
+        file.inode().fops().write(file, &mut reader, &mut { pos })
+    };
+
+    assert_eq!(write(&file, 0, 100), Ok(100));
+    assert_eq!((file.inode().size(), file.inode().blocks()), (100, 1));
+    assert_eq!(fs::statfs(&sb).unwrap(), created);
+
+    assert_eq!(write(&file, 0, 5000), Ok(5000));
+    assert_eq!((file.inode().size(), file.inode().blocks()), (5000, 2));
+
+    // Nothing is allocated for data that faults before it is copied.
+    let grown = fs::statfs(&sb).unwrap();
+    assert_eq!(write(&file, 8192, 0), Err(EFAULT));
+    assert_eq!((file.inode().size(), file.inode().blocks()), (5000, 2));
+    assert_eq!(fs::statfs(&sb).unwrap(), grown);
+
+    let mut buf = [0u8; 8192];
+    let mut pos = 0;
+    let mut writer = user::Writer::new(&mut buf).faulting_at(4100);
+    assert_eq!(
+        file.inode().fops().read(&file, &mut writer, &mut pos),
+        Ok(4100)
+    );
+    assert_eq!((pos, &buf[..4100]), (4100, &data[..4100]));
+
+    let mut writer = user::Writer::new(&mut buf).faulting_at(0);
+    assert_eq!(
+        file.inode().fops().read(&file, &mut writer, &mut pos),
+        Err(EFAULT)
+    );
+    assert_eq!(pos, 4100);
+
+    drop((file, root));
+    fs::kill_sb(sb).unwrap();
+}
+
+#[test]
+fn every_crash_state_mounts() {
+    let device = Arc::new(CrashDevice::new(device().contents()));
+    let sb = fs::mount::<RustEzFs>(Some(device.clone() as Arc<dyn BlockDevice>), "").unwrap();
+    let root = sb.root().unwrap();
+    let write = |file: &File<RustEzFs>, data: &[u8]| {
+        file.inode()
+            .fops()
+            .write(file, &mut user::Reader::new(data), &mut 0)
+            .unwrap()
+    };
+
+    write(&create(&root, b"a"), b"hello");
+    writeback::sync_filesystem(&sb).unwrap();
+    let synced = device.log_len();
+
+    write(&create(&root, b"b"), &[7; 6000]);
+    let dir = root.inode().unwrap();
+    dir.ops().mkdir(&dir.lock(), b"d", S_IFDIR | 0o755).unwrap();
+    dir.ops().unlink(&dir.lock(), b"b").unwrap();
+
+    drop((dir, root));
+    fs::kill_sb(sb).unwrap();
+
+    let states = device.crash_states(4);
+    assert!(states.len() > device.log_len());
+
+    for state in states {
+        let crashed: Arc<dyn BlockDevice> = Arc::new(MemDevice::from_vec(state.image));
+        let sb = fs::mount::<RustEzFs>(Some(crashed), "").unwrap();
+        let root = sb.root().unwrap();
+
+        for (name, _) in entries(&root).into_iter().skip(2) {
+            assert!(matches!(dentry::walk(&root, &name), Ok(_) | Err(ENOENT)));
+        }
+
+        // Everything before the flush survives whatever happens after it.
+        if state.prefix >= synced {
+            let file = File::open(dentry::walk(&root, b"a").unwrap()).unwrap();
+            let mut buf = [0u8; 8];
+            let read = file
+                .inode()
+                .fops()
+                .read(&file, &mut user::Writer::new(&mut buf), &mut 0)
+                .unwrap();
+            assert_eq!(&buf[..read], b"hello", "{:?}", state.dropped);
+        }
+
+        drop(root);
+        fs::kill_sb(sb).unwrap();
+    }
+}
+
+fn names(vfs: &Vfs<RustEzFs>, path: &[u8]) -> Vec<Vec<u8>> {
+    vfs.readdir(path)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.name)
+        .collect()
+}
+
+#[test]
+fn files_are_reached_by_path() {
+    let device = device();
+    let vfs = Vfs::<RustEzFs>::mount(Some(device.clone() as Arc<dyn BlockDevice>), "").unwrap();
+
+    let file = vfs.open(b"/a", O_CREAT | O_WRONLY, 0o644).unwrap();
+    assert_eq!(vfs.write(&file, b"hello"), Ok(5));
+    assert_eq!(vfs.write(&file, b", world"), Ok(7));
+    drop(file);
+    assert_eq!(vfs.open(b"a", O_CREAT | O_EXCL, 0o644).err(), Some(EEXIST));
+
+    vfs.mkdir(b"/d", 0o755).unwrap();
+    assert_eq!(vfs.mkdir(b"/d/", 0o755), Err(EEXIST));
+    assert_eq!(vfs.stat(b"/d").unwrap().nlink, 2);
+    assert_eq!(vfs.stat(b"/").unwrap().nlink, 3);
+
+    vfs.rename(b"/a", b"/d/b").unwrap();
+    assert_eq!(vfs.stat(b"/a").err(), Some(ENOENT));
+    assert_eq!(vfs.stat(b"/d/b").unwrap().size, 12);
+    assert_eq!(names(&vfs, b"/d"), [&b"."[..], b"..", b"b"]);
+    assert_eq!(vfs.rename(b"/d", b"/d/e"), Err(EINVAL));
+
+    let file = vfs.open(b"/d/b", O_RDONLY, 0).unwrap();
+    let mut buf = [0u8; 8];
+    assert_eq!(vfs.read(&file, &mut buf), Ok(8));
+    assert_eq!(vfs.read(&file, &mut buf), Ok(4));
+    assert_eq!(&buf[..4], b"orld");
+    drop(file);
+
+    let file = vfs.open(b"/d/b", O_WRONLY | O_APPEND, 0).unwrap();
+    assert_eq!(vfs.write(&file, b"!"), Ok(1));
+    assert_eq!(vfs.stat(b"/d/b").unwrap().size, 13);
+    drop(file);
+
+    drop(vfs.open(b"/d/b", O_RDWR | O_TRUNC, 0).unwrap());
+    assert_eq!(vfs.stat(b"/d/b").unwrap().size, 0);
+
+    assert_eq!(vfs.unlink(b"/d"), Err(EISDIR));
+    assert_eq!(vfs.rmdir(b"/d"), Err(ENOTEMPTY));
+    vfs.unlink(b"/d/b").unwrap();
+    assert_eq!(vfs.stat(b"/d/b").err(), Some(ENOENT));
+    vfs.rmdir(b"/d").unwrap();
+    assert_eq!(names(&vfs, b"/"), [&b"."[..], b".."]);
+
+    vfs.mkdir(b"/kept", 0o700).unwrap();
+    vfs.umount().unwrap();
+
+    let vfs = Vfs::<RustEzFs>::mount(Some(device as Arc<dyn BlockDevice>), "ro").unwrap();
+    assert_eq!(names(&vfs, b"/"), [&b"."[..], b"..", b"kept"]);
+    assert_eq!(vfs.stat(b"/kept").unwrap().mode, S_IFDIR | 0o700);
+    assert_eq!(vfs.open(b"/new", O_CREAT, 0o644).err(), Some(EROFS));
+    assert_eq!(vfs.rmdir(b"/kept"), Err(EROFS));
+    vfs.umount().unwrap();
+}
+
+#[test]
+fn read_only_mounts_leave_the_device_untouched() {
+    let device = device();
+    let vfs = Vfs::<RustEzFs>::mount(Some(device.clone() as Arc<dyn BlockDevice>), "").unwrap();
+    let file = vfs.open(b"/f", O_CREAT | O_WRONLY, 0o644).unwrap();
+    vfs.write(&file, b"data").unwrap();
+    drop(file);
+    vfs.umount().unwrap();
+    let image = device.contents();
+
+    time::set_clock(Some(Timespec::new(4_000_000_000, 0).unwrap()));
+    let vfs = Vfs::<RustEzFs>::mount(Some(device.clone() as Arc<dyn BlockDevice>), "ro").unwrap();
+    let file = vfs.open(b"/f", O_RDONLY, 0).unwrap();
+    assert_eq!(vfs.read(&file, &mut [0; 8]), Ok(4));
+    drop(file);
+    vfs.umount().unwrap();
+    time::set_clock(None);
+
+    assert!(device.contents() == image);
+}
+
+#[test]
+fn permissions_are_enforced() {
+    let vfs = Vfs::<RustEzFs>::mount(Some(device() as Arc<dyn BlockDevice>), "").unwrap();
+    vfs.mkdir(b"/locked", 0o755).unwrap();
+    let file = vfs
+        .open(b"/locked/secret", O_CREAT | O_WRONLY, 0o600)
+        .unwrap();
+    vfs.write(&file, b"root only").unwrap();
+    drop(file);
+    vfs.mkdir(b"/shared", 0o777).unwrap();
+    vfs.mkdir(b"/private", 0o700).unwrap();
+    drop(vfs.open(b"/private/f", O_CREAT, 0o644).unwrap());
+
+    // Opened as root, the file keeps root's credentials.
+    let root_file = vfs.open(b"/locked/secret", O_RDONLY, 0).unwrap();
+    let user = cred::override_creds(Arc::new(Credentials::user(1000, 1000)));
+    let mut buf = [0u8; 16];
+    assert_eq!(vfs.read(&root_file, &mut buf), Ok(9));
+
+    assert_eq!(vfs.open(b"/locked/secret", O_RDONLY, 0).err(), Some(EACCES));
+    assert_eq!(
+        vfs.open(b"/locked/secret", O_WRONLY | O_TRUNC, 0).err(),
+        Some(EACCES)
+    );
+    assert_eq!(vfs.stat(b"/locked/secret").unwrap().size, 9);
+
+    let secret = vfs.lookup_at(vfs.root(), b"locked").unwrap();
+    let secret = vfs.lookup_at(&secret, b"secret").unwrap().inode().unwrap();
+    let attr = |attr: Attr| vfs.setattr(&secret, &attr);
+    assert_eq!(
+        attr(Attr {
+            size: Some(0),
+            ..Attr::default()
+        }),
+        Err(EACCES)
+    );
+    assert_eq!(
+        attr(Attr {
+            mode: Some(0o666),
+            ..Attr::default()
+        }),
+        Err(EPERM)
+    );
+    assert_eq!(
+        attr(Attr {
+            uid: Some(1000),
+            ..Attr::default()
+        }),
+        Err(EPERM)
+    );
+    assert_eq!((secret.size(), secret.mode()), (9, S_IFREG | 0o600));
+
+    let locked = vfs
+        .lookup_at(vfs.root(), b"locked")
+        .unwrap()
+        .inode()
+        .unwrap();
+    assert_eq!(
+        locked.ops().link(&secret, &locked.lock(), b"alias"),
+        Err(EACCES)
+    );
+    assert_eq!(vfs.stat(b"/locked/alias").err(), Some(ENOENT));
+    assert_eq!(secret.nlink(), 1);
+
+    assert_eq!(vfs.unlink(b"/locked/secret"), Err(EACCES));
+    assert_eq!(vfs.open(b"/locked/new", O_CREAT, 0o644).err(), Some(EACCES));
+    assert_eq!(vfs.mkdir(b"/locked/new", 0o755), Err(EACCES));
+
+    // The entry is cached, but searching the directory is still checked.
+    assert_eq!(vfs.stat(b"/private/f").err(), Some(EACCES));
+
+    let file = vfs.open(b"/shared/mine", O_CREAT | O_RDWR, 0o600).unwrap();
+    assert_eq!(vfs.write(&file, b"mine"), Ok(4));
+    let stat = vfs.stat(b"/shared/mine").unwrap();
+    assert_eq!((stat.uid, stat.gid), (1000, 1000));
+    vfs.rename(b"/shared/mine", b"/shared/ours").unwrap();
+
+    // Access is checked once, when opening, so creating a read-only file still gives a
+    // writable descriptor.
+    let file = vfs.open(b"/shared/ro", O_CREAT | O_WRONLY, 0o444).unwrap();
+    assert_eq!(vfs.write(&file, b"x"), Ok(1));
+    assert_eq!(vfs.open(b"/shared/ro", O_WRONLY, 0).err(), Some(EACCES));
+    drop((file, user));
+
+    let other = cred::override_creds(Arc::new(Credentials::user(1001, 1001)));
+    assert_eq!(vfs.open(b"/shared/ours", O_RDONLY, 0).err(), Some(EACCES));
+    vfs.unlink(b"/shared/ours").unwrap();
+    drop(other);
+
+    vfs.umount().unwrap();
+}
@@ -1,10 +1,13 @@
 use crate::RustEzFs;
 use crate::defs::*;
+use crate::options::EzfsMountOpts;
 use crate::sb::{Bitmap, EzfsSuperblock, EzfsSuperblockData};
+use kernel::block::MemDevice;
 use kernel::fs::FileSystem;
-use kernel::inode::{INode, Mapper};
+use kernel::inode::Mapper;
+use kernel::sb::{New, SuperBlock};
 
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 #[kani::proof]
 fn verify_magic_number_logic_in_fill_super() {
@@ -31,11 +34,8 @@ fn verify_inode_allocation() {
             free_data_blocks: Bitmap::new([0; (EZFS_MAX_DATA_BLKS / 32) + 1]),
             zero_data_blocks: Bitmap::new([0; (EZFS_MAX_DATA_BLKS / 32) + 1]),
         }),
-        mapper: Mapper::<RustEzFs> {
-            inode: INode::<RustEzFs> { ino: 0, data: None },
-            begin: 0,
-            end: 4096,
-        },
+        opts: Mutex::new(EzfsMountOpts::default()),
+        mapper: Mapper::<RustEzFs>::new(Arc::new(MemDevice::new(4096)), 0, 4096),
     };
 
     let res1 = RustEzFs::allocate_inode(&sb);
@@ -61,11 +61,8 @@ fn verify_inode_allocated() {
             free_data_blocks: Bitmap::new([0; (EZFS_MAX_DATA_BLKS / 32) + 1]),
             zero_data_blocks: Bitmap::new([0; (EZFS_MAX_DATA_BLKS / 32) + 1]),
         }),
-        mapper: Mapper::<RustEzFs> {
-            inode: INode::<RustEzFs> { ino: 0, data: None },
-            begin: 0,
-            end: 4096,
-        },
+        opts: Mutex::new(EzfsMountOpts::default()),
+        mapper: Mapper::<RustEzFs>::new(Arc::new(MemDevice::new(4096)), 0, 4096),
     };
 
     let ino: u64 = kani::any();
@@ -95,11 +92,8 @@ fn verify_inode_deallocation() {
             free_data_blocks: Bitmap::new([0; (EZFS_MAX_DATA_BLKS / 32) + 1]),
             zero_data_blocks: Bitmap::new([0; (EZFS_MAX_DATA_BLKS / 32) + 1]),
         }),
-        mapper: Mapper::<RustEzFs> {
-            inode: INode::<RustEzFs> { ino: 0, data: None },
-            begin: 0,
-            end: 4096,
-        },
+        opts: Mutex::new(EzfsMountOpts::default()),
+        mapper: Mapper::<RustEzFs>::new(Arc::new(MemDevice::new(4096)), 0, 4096),
     };
 
     let ino: u64 = kani::any();
@@ -129,11 +123,8 @@ fn verify_max_blocks() {
             free_data_blocks: Bitmap::new([0; (EZFS_MAX_INODES / 32) + 1]),
             zero_data_blocks: Bitmap::new([0; (EZFS_MAX_INODES / 32) + 1]),
         }),
-        mapper: Mapper::<RustEzFs> {
-            inode: INode::<RustEzFs> { ino: 0, data: None },
-            begin: 0,
-            end: 4096,
-        },
+        opts: Mutex::new(EzfsMountOpts::default()),
+        mapper: Mapper::<RustEzFs>::new(Arc::new(MemDevice::new(4096)), 0, 4096),
     };
 
     RustEzFs::max_blocks(&sb);
@@ -151,11 +142,8 @@ fn verify_data_block_allocation() {
             free_data_blocks: kani::any(),
             zero_data_blocks: kani::any(),
         }),
-        mapper: Mapper::<RustEzFs> {
-            inode: INode::<RustEzFs> { ino: 0, data: None },
-            begin: 0,
-            end: 4096,
-        },
+        opts: Mutex::new(EzfsMountOpts::default()),
+        mapper: Mapper::<RustEzFs>::new(Arc::new(MemDevice::new(4096)), 0, 4096),
     };
 
     let res1 = RustEzFs::allocate_data_block(&sb);
@@ -182,11 +170,8 @@ fn verify_data_block_deallocation() {
             free_data_blocks: kani::any(),
             zero_data_blocks: kani::any(),
         }),
-        mapper: Mapper::<RustEzFs> {
-            inode: INode::<RustEzFs> { ino: 0, data: None },
-            begin: 0,
-            end: 4096,
-        },
+        opts: Mutex::new(EzfsMountOpts::default()),
+        mapper: Mapper::<RustEzFs>::new(Arc::new(MemDevice::new(4096)), 0, 4096),
     };
 
     let start = kani::any();
@@ -253,3 +238,34 @@ fn verify_bitmap_complete() {
     let _ = bitmap.clear_bit(idx);
     kani::assert(bitmap.inner == after_first, "Clear is idempotent");
 }
+
+#[kani::proof]
+fn verify_iget_models_cached_inodes() {
+    let ezfs_sb = EzfsSuperblock {
+        version: 1,
+        magic: 0x4118,
+        disk_blocks: kani::any(),
+        data: Mutex::new(EzfsSuperblockData {
+            free_inodes: kani::any(),
+            free_data_blocks: Bitmap::new([0; (EZFS_MAX_DATA_BLKS / 32) + 1]),
+            zero_data_blocks: Bitmap::new([0; (EZFS_MAX_DATA_BLKS / 32) + 1]),
+        }),
+        opts: Mutex::new(EzfsMountOpts::default()),
+        mapper: Mapper::<RustEzFs>::new(Arc::new(MemDevice::new(4096)), 0, 4096),
+    };
+    let sb = SuperBlock::<RustEzFs, New>::new(None).ready(Box::new(ezfs_sb));
+
+    let ino: usize = kani::any();
+    kani::assume(ino >= EZFS_ROOT_INODE_NUMBER && ino <= EZFS_MAX_INODES);
+
+    // Only block 0 is mapped, so reading the inode store fails and a successful `iget` must
+    // come from the cache.
+    if let Ok(inode) = RustEzFs::iget(&sb, ino) {
+        kani::assert(inode.ino() == ino, "iget returns the requested inode");
+        kani::assert(sb.cached_inodes() == 1, "the inode is cached");
+        kani::assert(
+            u32::from(inode.data().mode()) == inode.mode(),
+            "attributes come from the disk inode",
+        );
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::BTreeMap;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::PAGE_SIZE;
+use crate::file::File;
+use crate::fs::{FileSystem, Offset};
+use crate::inode::INode;
+use crate::types::code::{EFAULT, EFBIG, EINVAL, EIO};
+use crate::types::{ARef, Result};
+use crate::user;
+
+/// One page of a file's contents, the equivalent of `struct folio`.
+pub struct Folio {
+    index: u64,
+    /// Locking the data is the equivalent of the folio lock.
+    data: Mutex<Box<[u8; PAGE_SIZE]>>,
+    uptodate: AtomicBool,
+    dirty: AtomicBool,
+}
+
+impl Folio {
+    fn new(index: u64) -> Self {
+        Self {
+            index,
+            data: Mutex::new(Box::new([0; PAGE_SIZE])),
+            uptodate: AtomicBool::new(false),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Index of the page in the file.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Offset of the first byte of the page in the file.
+    pub fn pos(&self) -> Offset {
+        self.index as Offset * PAGE_SIZE as Offset
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Box<[u8; PAGE_SIZE]>> {
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether the contents have been read from the backing store, or fully written.
+    pub fn is_uptodate(&self) -> bool {
+        self.uptodate.load(Ordering::Acquire)
+    }
+
+    pub fn mark_uptodate(&self) {
+        self.uptodate.store(true, Ordering::Release)
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Acquire)
+    }
+}
+
+/// The page cache of an inode, the equivalent of `struct address_space`.
+pub struct AddressSpace<T: FileSystem + ?Sized> {
+    a_ops: Ops<T>,
+    pages: Mutex<BTreeMap<u64, ARef<Folio>>>,
+}
+
+impl<T: FileSystem + ?Sized> AddressSpace<T> {
+    pub(crate) fn new(a_ops: Ops<T>) -> Self {
+        Self {
+            a_ops,
+            pages: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn ops(&self) -> Ops<T> {
+        self.a_ops
+    }
+
+    fn pages(&self) -> MutexGuard<'_, BTreeMap<u64, ARef<Folio>>> {
+        self.pages.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Returns the cached page `index`, if any.
+    pub fn find_folio(&self, index: u64) -> Option<ARef<Folio>> {
+        self.pages().get(&index).cloned()
+    }
+
+    /// Returns the cached page `index`, adding a page that is not up to date if there is none.
+    pub fn grab_folio(&self, index: u64) -> ARef<Folio> {
+        self.pages()
+            .entry(index)
+            .or_insert_with(|| Arc::new(Folio::new(index)))
+            .clone()
+    }
+
+    /// Number of cached pages.
+    pub fn nr_pages(&self) -> usize {
+        self.pages().len()
+    }
+
+    /// The cached pages that must be written back.
+    pub fn dirty_folios(&self) -> Vec<ARef<Folio>> {
+        self.pages()
+            .values()
+            .filter(|folio| folio.is_dirty())
+            .cloned()
+            .collect()
+    }
+
+    /// Drops every cached page from `index` on, the equivalent of `truncate_inode_pages`.
+    ///
+    /// Dirty pages are dropped without being written back.
+    pub fn truncate(&self, index: u64) {
+        self.pages().split_off(&index);
+    }
+}
+
+/// Address space operations, the equivalent of `struct address_space_operations`.
+///
+/// Only [`Operations::read_folio`] and [`Operations::write_folio`] touch the backing store; the
+/// write path defaults to [`simple_write_begin`] and [`simple_write_end`].
+pub trait Operations {
+    type FileSystem: FileSystem + ?Sized;
+
+    /// Fills `data`, the contents of `folio`, from the backing store.
+    fn read_folio(
+        _inode: &INode<Self::FileSystem>,
+        _folio: &Folio,
+        _data: &mut [u8; PAGE_SIZE],
+    ) -> Result {
+        Err(EIO)
+    }
+
+    /// Writes `data`, the contents of the dirty `folio`, to the backing store.
+    fn write_folio(
+        _inode: &INode<Self::FileSystem>,
+        _folio: &Folio,
+        _data: &[u8; PAGE_SIZE],
+    ) -> Result {
+        Err(EIO)
+    }
+
+    /// Prepares the page holding `pos` for a write of `len` bytes that stays within the page.
+    fn write_begin(file: &File<Self::FileSystem>, pos: Offset, len: usize) -> Result<ARef<Folio>> {
+        simple_write_begin(file, pos, len)
+    }
+
+    /// Completes a write of `copied` bytes at `pos` into `folio`, returning the bytes written.
+    fn write_end(
+        file: &File<Self::FileSystem>,
+        pos: Offset,
+        copied: usize,
+        folio: &Folio,
+    ) -> Result<usize> {
+        simple_write_end(file, pos, copied, folio)
+    }
+
+    /// Marks `folio` dirty, returning whether it was clean.
+    fn dirty_folio(_inode: &INode<Self::FileSystem>, folio: &Folio) -> bool {
+        !folio.dirty.swap(true, Ordering::AcqRel)
+    }
+}
+
+/// A table of address space operations, created from an [`Operations`] implementation.
+pub struct Ops<T: FileSystem + ?Sized>(&'static Table<T>);
+
+impl<T: FileSystem + ?Sized> Clone for Ops<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: FileSystem + ?Sized> Copy for Ops<T> {}
+
+#[allow(clippy::type_complexity)]
+struct Table<T: FileSystem + ?Sized> {
+    read_folio: fn(&INode<T>, &Folio, &mut [u8; PAGE_SIZE]) -> Result,
+    write_folio: fn(&INode<T>, &Folio, &[u8; PAGE_SIZE]) -> Result,
+    write_begin: fn(&File<T>, Offset, usize) -> Result<ARef<Folio>>,
+    write_end: fn(&File<T>, Offset, usize, &Folio) -> Result<usize>,
+    dirty_folio: fn(&INode<T>, &Folio) -> bool,
+}
+
+struct TableFor<U: ?Sized>(PhantomData<U>);
+
+impl<U: Operations + ?Sized> TableFor<U> {
+    const TABLE: Table<U::FileSystem> = Table {
+        read_folio: U::read_folio,
+        write_folio: U::write_folio,
+        write_begin: U::write_begin,
+        write_end: U::write_end,
+        dirty_folio: U::dirty_folio,
+    };
+}
+
+/// Operations for inodes without a backing store.
+struct NoOps<T: ?Sized>(PhantomData<T>);
+
+impl<T: FileSystem + ?Sized> Operations for NoOps<T> {
+    type FileSystem = T;
+}
+
+impl<T: FileSystem + ?Sized> Ops<T> {
+    pub const fn new<U: Operations<FileSystem = T> + ?Sized>() -> Self {
+        Self(&TableFor::<U>::TABLE)
+    }
+
+    pub const fn empty() -> Self {
+        Self::new::<NoOps<T>>()
+    }
+
+    pub fn read_folio(
+        &self,
+        inode: &INode<T>,
+        folio: &Folio,
+        data: &mut [u8; PAGE_SIZE],
+    ) -> Result {
+        (self.0.read_folio)(inode, folio, data)
+    }
+
+    pub fn write_folio(&self, inode: &INode<T>, folio: &Folio, data: &[u8; PAGE_SIZE]) -> Result {
+        (self.0.write_folio)(inode, folio, data)
+    }
+
+    pub fn write_begin(&self, file: &File<T>, pos: Offset, len: usize) -> Result<ARef<Folio>> {
+        (self.0.write_begin)(file, pos, len)
+    }
+
+    pub fn write_end(
+        &self,
+        file: &File<T>,
+        pos: Offset,
+        copied: usize,
+        folio: &Folio,
+    ) -> Result<usize> {
+        (self.0.write_end)(file, pos, copied, folio)
+    }
+
+    pub fn dirty_folio(&self, inode: &INode<T>, folio: &Folio) -> bool {
+        (self.0.dirty_folio)(inode, folio)
+    }
+}
+
+/// Returns page `index` of `inode`, reading it if it is not up to date.
+pub fn read_mapping_folio<T: FileSystem + ?Sized>(
+    inode: &INode<T>,
+    index: u64,
+) -> Result<ARef<Folio>> {
+    let mapping = inode.mapping();
+    let folio = mapping.grab_folio(index);
+
+    if !folio.is_uptodate() {
+        let mut data = folio.lock();
+
+        // Another reader may have filled the page while we waited for the lock.
+        if !folio.is_uptodate() {
+            mapping.a_ops.read_folio(inode, &folio, &mut data)?;
+            folio.mark_uptodate();
+        }
+    }
+
+    Ok(folio)
+}
+
+/// Marks `folio` of `inode` dirty through the filesystem's [`Operations::dirty_folio`], queueing
+/// the inode for writeback if the folio was clean.
+pub fn folio_mark_dirty<T: FileSystem + ?Sized>(inode: &INode<T>, folio: &Folio) -> bool {
+    let newly_dirty = inode.mapping().a_ops.dirty_folio(inode, folio);
+
+    if newly_dirty {
+        inode.mark_dirty();
+    }
+
+    newly_dirty
+}
+
+fn page_range(pos: Offset, len: usize) -> Result<(u64, usize, usize)> {
+    let pos: u64 = pos.try_into().map_err(|_| EINVAL)?;
+    let index = pos / PAGE_SIZE as u64;
+    let offset = (pos % PAGE_SIZE as u64) as usize;
+
+    Ok((index, offset, len.min(PAGE_SIZE - offset)))
+}
+
+/// The default [`Operations::write_begin`]: returns the page, reading it first unless the write
+/// covers it completely or it lies past the end of the file.
+pub fn simple_write_begin<T: FileSystem + ?Sized>(
+    file: &File<T>,
+    pos: Offset,
+    len: usize,
+) -> Result<ARef<Folio>> {
+    let inode = file.inode();
+    let (index, offset, len) = page_range(pos, len)?;
+    let folio = inode.mapping().grab_folio(index);
+
+    if folio.is_uptodate() || (offset == 0 && len == PAGE_SIZE) {
+        return Ok(folio);
+    }
+
+    if folio.pos() as u64 >= inode.size() {
+        folio.lock().fill(0);
+        folio.mark_uptodate();
+        return Ok(folio);
+    }
+
+    read_mapping_folio(inode, index)
+}
+
+/// The default [`Operations::write_end`]: dirties the page and extends the file if the write went
+/// past its end.
+pub fn simple_write_end<T: FileSystem + ?Sized>(
+    file: &File<T>,
+    pos: Offset,
+    copied: usize,
+    folio: &Folio,
+) -> Result<usize> {
+    let inode = file.inode();
+
+    // A page that was not read in was meant to be overwritten completely; after a short copy,
+    // part of it holds neither the old nor the new data.
+    if copied == 0 || (!folio.is_uptodate() && copied < PAGE_SIZE) {
+        return Ok(0);
+    }
+
+    folio.mark_uptodate();
+    folio_mark_dirty(inode, folio);
+
+    let end = (pos as u64).checked_add(copied as u64).ok_or(EFBIG)?;
+    if end > inode.size() {
+        inode.set_size(end);
+    }
+
+    Ok(copied)
+}
+
+/// Reads from the page cache of `file` at `*offset`, the equivalent of `filemap_read`.
+///
+/// Stops early at a fault in `writer` or an error reading a page, and fails only if nothing was
+/// copied.
+pub fn generic_file_read<T: FileSystem + ?Sized>(
+    file: &File<T>,
+    writer: &mut user::Writer<'_>,
+    offset: &mut Offset,
+) -> Result<usize> {
+    let inode = file.inode();
+    let pos: u64 = (*offset).try_into().map_err(|_| EINVAL)?;
+    let size = inode.size();
+
+    if pos >= size {
+        return Ok(0);
+    }
+
+    let len = (writer.len() as u64).min(size - pos) as usize;
+    let mut done = 0;
+
+    while done < len {
+        let (index, off, n) = page_range(*offset + done as Offset, len - done)?;
+        let folio = match read_mapping_folio(inode, index) {
+            Ok(folio) => folio,
+            Err(e) if done == 0 => return Err(e),
+            Err(_) => break,
+        };
+
+        let copied = writer.write_partial(&folio.lock()[off..off + n]);
+        done += copied;
+
+        if copied < n {
+            break;
+        }
+    }
+
+    if done == 0 && len > 0 {
+        return Err(EFAULT);
+    }
+
+    *offset += done as Offset;
+    Ok(done)
+}
+
+/// Writes to the page cache of `file` at `*offset` through [`Operations::write_begin`] and
+/// [`Operations::write_end`], the equivalent of `generic_perform_write`.
+///
+/// Stops early at a fault in `reader` or an error preparing a page, and fails only if nothing was
+/// copied. Fails with `EFBIG` if the write would extend the file past the filesystem's `maxbytes`.
+pub fn generic_perform_write<T: FileSystem + ?Sized>(
+    file: &File<T>,
+    reader: &mut user::Reader<'_>,
+    offset: &mut Offset,
+) -> Result<usize> {
+    let inode = file.inode();
+    let a_ops = inode.mapping().a_ops;
+    let len = reader.len();
+    let mut done = 0;
+
+    let end = u64::try_from(*offset)?
+        .checked_add(len as u64)
+        .ok_or(EFBIG)?;
+    if end > inode.super_block().maxbytes() {
+        return Err(EFBIG);
+    }
+
+    while done < len {
+        let pos = *offset + done as Offset;
+        let (_, off, n) = page_range(pos, len - done)?;
+
+        // Only ask for as much as can be copied, so that `write_begin` does not prepare more.
+        let n = reader.fault_in(n);
+        if n == 0 {
+            break;
+        }
+
+        let written = a_ops.write_begin(file, pos, n).and_then(|folio| {
+            let copied = reader.read_partial(&mut folio.lock()[off..off + n]);
+            a_ops.write_end(file, pos, copied, &folio)
+        });
+        let written = match written {
+            Ok(written) => written,
+            Err(e) if done == 0 => return Err(e),
+            Err(_) => break,
+        };
+        done += written;
+
+        if written < n {
+            break;
+        }
+    }
+
+    if done == 0 && len > 0 {
+        return Err(EFAULT);
+    }
+
+    *offset += done as Offset;
+    Ok(done)
+}
+
+/// Writes back every dirty page of `inode`, the equivalent of `filemap_write_and_wait`.
+pub fn filemap_write_and_wait<T: FileSystem + ?Sized>(inode: &INode<T>) -> Result {
+    let mapping = inode.mapping();
+
+    for folio in mapping.dirty_folios() {
+        let data = folio.lock();
+
+        if folio.is_dirty() {
+            mapping.a_ops.write_folio(inode, &folio, &data)?;
+            folio.dirty.store(false, Ordering::Release);
+        }
+    }
+
+    Ok(())
+}
+
+/// Drops the cached pages past `size` and zeroes the tail of the page that `size` falls in.
+pub fn truncate_pagecache<T: FileSystem + ?Sized>(inode: &INode<T>, size: u64) {
+    let mapping = inode.mapping();
+    let index = size.div_ceil(PAGE_SIZE as u64);
+
+    mapping.truncate(index);
+
+    let offset = (size % PAGE_SIZE as u64) as usize;
+    if offset != 0
+        && let Some(folio) = mapping.find_folio(index - 1)
+    {
+        folio.lock()[offset..].fill(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{PAGES_READ, new_file, super_block};
+
+    #[test]
+    fn reads_are_cached() {
+        let sb = super_block();
+        let file = new_file(&sb, 2);
+        file.inode().set_size(2 * PAGE_SIZE as u64);
+
+        let mut buf = [0u8; 16];
+        let mut pos = PAGE_SIZE as Offset - 8;
+        let read = generic_file_read(&file, &mut user::Writer::new(&mut buf), &mut pos).unwrap();
+
+        // Each page is filled with its index.
+        assert_eq!(read, 16);
+        assert_eq!(buf, [[0u8; 8], [1u8; 8]].concat()[..]);
+        assert_eq!(file.inode().mapping().nr_pages(), 2);
+
+        let before = PAGES_READ.with(|n| n.get());
+        let mut pos = 0;
+        generic_file_read(&file, &mut user::Writer::new(&mut buf), &mut pos).unwrap();
+        assert_eq!(PAGES_READ.with(|n| n.get()), before);
+    }
+
+    #[test]
+    fn faults_shorten_copies() {
+        let sb = super_block();
+        let file = new_file(&sb, 2);
+        file.inode().set_size(2 * PAGE_SIZE as u64);
+
+        let mut buf = [0u8; 16];
+        let mut pos = PAGE_SIZE as Offset - 8;
+        let mut writer = user::Writer::new(&mut buf).faulting_at(12);
+        assert_eq!(generic_file_read(&file, &mut writer, &mut pos), Ok(12));
+        assert_eq!(pos, PAGE_SIZE as Offset + 4);
+
+        let mut writer = user::Writer::new(&mut buf).faulting_at(0);
+        assert_eq!(generic_file_read(&file, &mut writer, &mut pos), Err(EFAULT));
+        assert_eq!(pos, PAGE_SIZE as Offset + 4);
+
+        let mut pos = 2 * PAGE_SIZE as Offset;
+        let mut reader = user::Reader::new(&[7; 8]).faulting_at(5);
+        assert_eq!(generic_perform_write(&file, &mut reader, &mut pos), Ok(5));
+        assert_eq!(file.inode().size(), 2 * PAGE_SIZE as u64 + 5);
+
+        let mut reader = user::Reader::new(&[7; 8]).faulting_at(0);
+        assert_eq!(
+            generic_perform_write(&file, &mut reader, &mut pos),
+            Err(EFAULT)
+        );
+        assert_eq!(file.inode().size(), 2 * PAGE_SIZE as u64 + 5);
+    }
+
+    #[test]
+    fn writes_dirty_pages_and_extend_the_file() {
+        let sb = super_block();
+        let file = new_file(&sb, 3);
+        let inode = file.inode();
+
+        let mut pos = 10;
+        let written =
+            generic_perform_write(&file, &mut user::Reader::new(&[7; 8]), &mut pos).unwrap();
+        assert_eq!((written, pos, inode.size()), (8, 18, 18));
+
+        let mut far = crate::sb::MAX_NON_LFS as Offset;
+        assert_eq!(
+            generic_perform_write(&file, &mut user::Reader::new(&[7; 8]), &mut far),
+            Err(EFBIG)
+        );
+
+        let folio = inode.mapping().find_folio(0).unwrap();
+        assert!(folio.is_dirty());
+        assert_eq!(folio.lock()[..18], [&[0u8; 10][..], &[7u8; 8]].concat()[..]);
+
+        // The test filesystem cannot write back.
+        assert_eq!(filemap_write_and_wait(inode), Err(EIO));
+
+        truncate_pagecache(inode, 12);
+        assert_eq!(folio.lock()[10..18], [7, 7, 0, 0, 0, 0, 0, 0]);
+        truncate_pagecache(inode, 0);
+        assert_eq!(inode.mapping().nr_pages(), 0);
+    }
+}
@@ -0,0 +1,220 @@
+//! A block device that records its writes to replay the disk as a power loss would leave it.
+
+use std::sync::{Mutex, MutexGuard};
+
+use crate::block::{BlockDevice, MemDevice};
+use crate::types::Result;
+use crate::types::code::EINVAL;
+
+/// One operation that reached a [`CrashDevice`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LogEntry {
+    Write { offset: u64, data: Vec<u8> },
+    Flush,
+}
+
+/// The disk as it could be found after a crash.
+#[derive(Clone, Debug)]
+pub struct CrashState {
+    /// How many log entries had been issued.
+    pub prefix: usize,
+    /// Log indices of the unflushed writes that did not make it to the disk.
+    pub dropped: Vec<usize>,
+    pub image: Vec<u8>,
+}
+
+/// An in-memory device that logs every write and flush.
+///
+/// Writes issued before a flush are durable once the flush is issued. Writes after the last flush
+/// may be lost in any combination, which is how a disk that reorders its write cache behaves.
+pub struct CrashDevice {
+    initial: Vec<u8>,
+    current: MemDevice,
+    log: Mutex<Vec<LogEntry>>,
+}
+
+impl CrashDevice {
+    /// A device holding `image`, which is taken to be durable.
+    pub fn new(image: Vec<u8>) -> Self {
+        Self {
+            current: MemDevice::from_vec(image.clone()),
+            initial: image,
+            log: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn entries(&self) -> MutexGuard<'_, Vec<LogEntry>> {
+        self.log.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// What the device holds now, as if every write had reached the disk.
+    pub fn contents(&self) -> Vec<u8> {
+        self.current.contents()
+    }
+
+    pub fn log(&self) -> Vec<LogEntry> {
+        self.entries().clone()
+    }
+
+    pub fn log_len(&self) -> usize {
+        self.entries().len()
+    }
+
+    /// The disk after the first `prefix` log entries, without the writes at the indices in
+    /// `dropped`.
+    ///
+    /// Fails with `EINVAL` if `prefix` is past the end of the log or if `dropped` names anything
+    /// other than a write issued after the last flush of the prefix.
+    pub fn crash_state(&self, prefix: usize, dropped: &[usize]) -> Result<Vec<u8>> {
+        let log = self.entries();
+        let log = log.get(..prefix).ok_or(EINVAL)?;
+        let barrier = Self::barrier(log);
+
+        if dropped
+            .iter()
+            .any(|&i| i < barrier || !matches!(log.get(i), Some(LogEntry::Write { .. })))
+        {
+            return Err(EINVAL);
+        }
+
+        let mut image = self.initial.clone();
+
+        for (i, entry) in log.iter().enumerate() {
+            if let LogEntry::Write { offset, data } = entry
+                && !dropped.contains(&i)
+            {
+                let start = *offset as usize;
+                image[start..start + data.len()].copy_from_slice(data);
+            }
+        }
+
+        Ok(image)
+    }
+
+    /// Every distinct state a crash could leave the disk in.
+    ///
+    /// With `max_reordered` above zero, each state also comes in every variant where some of the
+    /// last `max_reordered` unflushed writes before the crash were lost.
+    pub fn crash_states(&self, max_reordered: usize) -> Vec<CrashState> {
+        let log = self.log();
+        let mut states = Vec::new();
+
+        for prefix in 0..=log.len() {
+            // A prefix ending in a flush leaves the disk as the prefix without it did.
+            if prefix > 0 && log[prefix - 1] == LogEntry::Flush {
+                continue;
+            }
+
+            // The last write is always kept: dropping it gives the states of the shorter prefix.
+            let end = prefix.saturating_sub(1);
+            let unflushed: Vec<usize> = (Self::barrier(&log[..end])..end)
+                .filter(|&i| log[i] != LogEntry::Flush)
+                .collect();
+            let candidates = &unflushed[unflushed.len().saturating_sub(max_reordered)..];
+
+            for mask in 0..1usize << candidates.len() {
+                let dropped: Vec<usize> = candidates
+                    .iter()
+                    .enumerate()
+                    .filter(|(bit, _)| mask & 1 << bit != 0)
+                    .map(|(_, &i)| i)
+                    .collect();
+
+                if let Ok(image) = self.crash_state(prefix, &dropped) {
+                    states.push(CrashState {
+                        prefix,
+                        dropped,
+                        image,
+                    });
+                }
+            }
+        }
+
+        states
+    }
+
+    /// The index just past the last flush in `log`.
+    fn barrier(log: &[LogEntry]) -> usize {
+        log.iter()
+            .rposition(|entry| *entry == LogEntry::Flush)
+            .map_or(0, |i| i + 1)
+    }
+}
+
+impl BlockDevice for CrashDevice {
+    fn size(&self) -> u64 {
+        self.current.size()
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        self.current.read_at(buf, offset)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let mut log = self.entries();
+        let written = self.current.write_at(buf, offset)?;
+
+        log.push(LogEntry::Write {
+            offset,
+            data: buf[..written].to_vec(),
+        });
+
+        Ok(written)
+    }
+
+    fn flush(&self) -> Result {
+        self.entries().push(LogEntry::Flush);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crash_states_respect_flushes() {
+        let dev = CrashDevice::new(vec![0; 4]);
+
+        dev.write_at(&[1], 0).unwrap();
+        dev.flush().unwrap();
+        dev.write_at(&[2], 1).unwrap();
+        dev.write_at(&[3], 2).unwrap();
+        dev.flush().unwrap();
+        assert_eq!(dev.log_len(), 5);
+        assert_eq!(dev.contents(), [1, 2, 3, 0]);
+
+        assert_eq!(dev.crash_state(0, &[]), Ok(vec![0; 4]));
+        assert_eq!(dev.crash_state(4, &[2]), Ok(vec![1, 0, 3, 0]));
+        assert_eq!(dev.crash_state(4, &[0]), Err(EINVAL));
+        assert_eq!(dev.crash_state(5, &[2]), Err(EINVAL));
+        assert_eq!(dev.crash_state(6, &[]), Err(EINVAL));
+
+        let images = |max_reordered| {
+            dev.crash_states(max_reordered)
+                .into_iter()
+                .map(|state| state.image)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            images(0),
+            [
+                vec![0; 4],
+                vec![1, 0, 0, 0],
+                vec![1, 2, 0, 0],
+                vec![1, 2, 3, 0]
+            ]
+        );
+        assert_eq!(
+            images(8),
+            [
+                vec![0; 4],
+                vec![1, 0, 0, 0],
+                vec![1, 2, 0, 0],
+                vec![1, 2, 3, 0],
+                vec![1, 0, 3, 0]
+            ]
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! A block device that injects I/O errors, for testing error paths deterministically.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::block::BlockDevice;
+use crate::types::Result;
+use crate::types::code::EIO;
+
+#[derive(Default)]
+struct Faults {
+    read_blocks: BTreeSet<u64>,
+    write_blocks: BTreeSet<u64>,
+    /// Reads and writes that may still succeed before all of them fail with `EIO`.
+    reads_left: Option<usize>,
+    writes_left: Option<usize>,
+    /// Reads starting in a block return at most this many bytes.
+    short_reads: BTreeMap<u64, usize>,
+    /// Bytes flipped with the given mask on every read, keyed by device offset.
+    corrupt: BTreeMap<u64, u8>,
+    reads: usize,
+    writes: usize,
+}
+
+/// Wraps a device and fails, shortens or corrupts its I/O as programmed.
+///
+/// Faults are set up through `&self` so that they can be changed while a filesystem is mounted on
+/// the device. Failing operations return `EIO` and leave the wrapped device untouched.
+pub struct FaultyDevice {
+    inner: Arc<dyn BlockDevice>,
+    block_size: u64,
+    faults: Mutex<Faults>,
+}
+
+impl FaultyDevice {
+    /// Wraps `inner`, numbering blocks of `block_size` bytes.
+    pub fn new(inner: Arc<dyn BlockDevice>, block_size: u64) -> Self {
+        Self {
+            inner,
+            block_size,
+            faults: Mutex::new(Faults::default()),
+        }
+    }
+
+    fn faults(&self) -> MutexGuard<'_, Faults> {
+        self.faults.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Fails every read that touches `block`.
+    pub fn fail_reads(&self, block: u64) {
+        self.faults().read_blocks.insert(block);
+    }
+
+    /// Fails every write that touches `block`.
+    pub fn fail_writes(&self, block: u64) {
+        self.faults().write_blocks.insert(block);
+    }
+
+    /// Lets `n` more reads succeed and fails all later ones.
+    pub fn fail_reads_after(&self, n: usize) {
+        self.faults().reads_left = Some(n);
+    }
+
+    /// Lets `n` more writes succeed and fails all later ones.
+    pub fn fail_writes_after(&self, n: usize) {
+        self.faults().writes_left = Some(n);
+    }
+
+    /// Cuts reads that start in `block` down to at most `len` bytes.
+    pub fn short_reads(&self, block: u64, len: usize) {
+        self.faults().short_reads.insert(block, len);
+    }
+
+    /// Flips the bits in `mask` of the byte at `offset` whenever it is read.
+    pub fn corrupt(&self, offset: u64, mask: u8) {
+        self.faults().corrupt.insert(offset, mask);
+    }
+
+    /// Removes all faults; the operation counts are kept.
+    pub fn clear(&self) {
+        let mut faults = self.faults();
+        let (reads, writes) = (faults.reads, faults.writes);
+
+        *faults = Faults {
+            reads,
+            writes,
+            ..Faults::default()
+        };
+    }
+
+    /// The number of reads that reached the wrapped device.
+    pub fn reads(&self) -> usize {
+        self.faults().reads
+    }
+
+    /// The number of writes that reached the wrapped device.
+    pub fn writes(&self) -> usize {
+        self.faults().writes
+    }
+
+    fn blocks(&self, offset: u64, len: usize) -> RangeInclusive<u64> {
+        let last = offset + (len as u64).max(1) - 1;
+        offset / self.block_size..=last / self.block_size
+    }
+
+    /// Takes one operation off `left`, failing if none are left.
+    fn take(left: &mut Option<usize>) -> Result {
+        match left {
+            Some(0) => Err(EIO),
+            Some(n) => {
+                *n -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl BlockDevice for FaultyDevice {
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let mut faults = self.faults();
+
+        if self
+            .blocks(offset, buf.len())
+            .any(|block| faults.read_blocks.contains(&block))
+        {
+            return Err(EIO);
+        }
+        Self::take(&mut faults.reads_left)?;
+
+        let len = match faults.short_reads.get(&(offset / self.block_size)) {
+            Some(&short) => buf.len().min(short),
+            None => buf.len(),
+        };
+
+        let read = self.inner.read_at(&mut buf[..len], offset)?;
+        faults.reads += 1;
+
+        for (&at, &mask) in faults.corrupt.range(offset..offset + read as u64) {
+            buf[(at - offset) as usize] ^= mask;
+        }
+
+        Ok(read)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let mut faults = self.faults();
+
+        if self
+            .blocks(offset, buf.len())
+            .any(|block| faults.write_blocks.contains(&block))
+        {
+            return Err(EIO);
+        }
+        Self::take(&mut faults.writes_left)?;
+
+        let written = self.inner.write_at(buf, offset)?;
+        faults.writes += 1;
+
+        Ok(written)
+    }
+
+    fn flush(&self) -> Result {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::MemDevice;
+
+    #[test]
+    fn faults_are_injected_as_programmed() {
+        let mem = Arc::new(MemDevice::from_vec((0..64).collect()));
+        let dev = FaultyDevice::new(mem.clone(), 16);
+        let mut buf = [0u8; 16];
+
+        dev.fail_reads(1);
+        assert_eq!(dev.read_at(&mut buf, 8), Err(EIO));
+        assert_eq!(dev.read_at(&mut buf, 32), Ok(16));
+
+        dev.short_reads(2, 4);
+        dev.corrupt(33, 0xff);
+        assert_eq!(dev.read_at(&mut buf, 32), Ok(4));
+        assert_eq!(buf[..4], [32, !33, 34, 35]);
+
+        dev.fail_writes(3);
+        assert_eq!(dev.write_at(&[0; 4], 46), Err(EIO));
+        assert_eq!(mem.contents()[46..50], [46, 47, 48, 49]);
+
+        dev.fail_writes_after(1);
+        assert_eq!(dev.write_at(&[0; 4], 0), Ok(4));
+        assert_eq!(dev.write_at(&[0; 4], 0), Err(EIO));
+
+        dev.fail_reads_after(0);
+        assert_eq!(dev.read_at(&mut buf, 0), Err(EIO));
+
+        dev.clear();
+        assert_eq!(dev.read_at(&mut buf, 16), Ok(16));
+        assert_eq!(buf[0], 16);
+        assert_eq!(dev.write_at(&[0; 4], 48), Ok(4));
+        assert_eq!((dev.reads(), dev.writes()), (3, 2));
+    }
+}
@@ -0,0 +1,175 @@
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::types::Result;
+use crate::types::code::ENOSPC;
+
+pub mod crash;
+pub mod fault;
+
+pub use crash::{CrashDevice, CrashState, LogEntry};
+pub use fault::FaultyDevice;
+
+pub trait BlockDevice: Send + Sync {
+    /// Size of the device in bytes.
+    fn size(&self) -> u64;
+
+    /// Reads into `buf` starting at byte `offset`, returning how many bytes were read.
+    ///
+    /// Reads that extend past the end of the device are truncated.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize>;
+
+    /// Writes `buf` starting at byte `offset`, returning how many bytes were written.
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize>;
+
+    /// Makes all previous writes durable.
+    fn flush(&self) -> Result;
+}
+
+pub struct MemDevice {
+    data: Mutex<Vec<u8>>,
+}
+
+impl MemDevice {
+    pub fn new(size: usize) -> Self {
+        Self::from_vec(vec![0; size])
+    }
+
+    pub fn from_vec(data: Vec<u8>) -> Self {
+        Self {
+            data: Mutex::new(data),
+        }
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.data.lock().map(|d| d.clone()).unwrap_or_default()
+    }
+}
+
+impl BlockDevice for MemDevice {
+    fn size(&self) -> u64 {
+        self.data.lock().map(|d| d.len() as u64).unwrap_or(0)
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let data = self.data.lock()?;
+        let start = usize::try_from(offset)?;
+
+        if start >= data.len() {
+            return Ok(0);
+        }
+
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+
+        Ok(len)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        let mut data = self.data.lock()?;
+        let start = usize::try_from(offset)?;
+
+        if start >= data.len() {
+            return Err(ENOSPC);
+        }
+
+        let len = buf.len().min(data.len() - start);
+        data[start..start + len].copy_from_slice(&buf[..len]);
+
+        Ok(len)
+    }
+
+    fn flush(&self) -> Result {
+        Ok(())
+    }
+}
+
+/// A block device backed by a disk image file.
+pub struct FileDevice {
+    file: File,
+    size: u64,
+}
+
+impl FileDevice {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        Self::from_file(file)
+    }
+
+    pub fn from_file(file: File) -> Result<Self> {
+        let size = file.metadata()?.len();
+
+        Ok(Self { file, size })
+    }
+}
+
+impl BlockDevice for FileDevice {
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        if offset >= self.size {
+            return Ok(0);
+        }
+
+        let len = buf.len().min((self.size - offset) as usize);
+        self.file.read_exact_at(&mut buf[..len], offset)?;
+
+        Ok(len)
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> Result<usize> {
+        if offset >= self.size {
+            return Err(ENOSPC);
+        }
+
+        let len = buf.len().min((self.size - offset) as usize);
+        self.file.write_all_at(&buf[..len], offset)?;
+
+        Ok(len)
+    }
+
+    fn flush(&self) -> Result {
+        Ok(self.file.sync_data()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mem_device_truncates_at_end() {
+        let dev = MemDevice::from_vec((0..16).collect());
+        let mut buf = [0u8; 8];
+
+        assert_eq!(dev.read_at(&mut buf, 12), Ok(4));
+        assert_eq!(&buf[..4], &[12, 13, 14, 15]);
+        assert_eq!(dev.read_at(&mut buf, 16), Ok(0));
+
+        assert_eq!(dev.write_at(&[0xff; 8], 12), Ok(4));
+        assert_eq!(dev.write_at(&[0xff; 8], 16), Err(ENOSPC));
+        assert_eq!(dev.contents()[12..], [0xff; 4]);
+    }
+
+    #[test]
+    fn file_device_round_trip() {
+        let path = std::env::temp_dir().join(format!("kernel-block-{}.img", std::process::id()));
+        std::fs::write(&path, [0u8; 8192]).unwrap();
+
+        let dev = FileDevice::open(&path).unwrap();
+        assert_eq!(dev.size(), 8192);
+        assert_eq!(dev.write_at(b"ezfs", 4096), Ok(4));
+        dev.flush().unwrap();
+
+        let mut buf = [0u8; 4];
+        assert_eq!(dev.read_at(&mut buf, 4096), Ok(4));
+        assert_eq!(&buf, b"ezfs");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+//! Block buffers of block-device filesystems, the equivalent of `struct buffer_head`.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use crate::block::BlockDevice;
+use crate::fs::FileSystem;
+use crate::sb::{New, SuperBlock};
+use crate::types::code::{EIO, ENOTBLK};
+use crate::types::{ARef, Result};
+
+/// One block of a device, read through [`sb_bread`].
+///
+/// Buffers are shared while referenced: reading a block that is already in use returns the same
+/// buffer. A buffer that is still dirty when the last reference goes is written back then.
+pub struct BufferHead {
+    blocknr: u64,
+    device: Arc<dyn BlockDevice>,
+    data: Mutex<Box<[u8]>>,
+    dirty: AtomicBool,
+}
+
+impl BufferHead {
+    pub fn blocknr(&self) -> u64 {
+        self.blocknr
+    }
+
+    pub fn size(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn offset(&self, size: usize) -> u64 {
+        self.blocknr * size as u64
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Box<[u8]>> {
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The contents of the block; callers that change them must call [`BufferHead::mark_dirty`].
+    pub fn data(&self) -> MutexGuard<'_, Box<[u8]>> {
+        self.lock()
+    }
+
+    /// The equivalent of `mark_buffer_dirty`.
+    pub fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Acquire)
+    }
+
+    /// Writes the block back if it is dirty, the equivalent of `sync_dirty_buffer`.
+    pub fn sync_dirty_buffer(&self) -> Result {
+        let data = self.lock();
+
+        if !self.dirty.swap(false, Ordering::AcqRel) {
+            return Ok(());
+        }
+
+        let res = self.device.write_at(&data, self.offset(data.len()));
+        if !matches!(res, Ok(n) if n == data.len()) {
+            self.mark_dirty();
+            return Err(res.err().unwrap_or(EIO));
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for BufferHead {
+    fn drop(&mut self) {
+        // Errors cannot be reported from here; callers that care sync first.
+        let _ = self.sync_dirty_buffer();
+    }
+}
+
+/// The buffers of a superblock that are in use, keyed by block number.
+#[derive(Default)]
+pub(crate) struct BufferCache {
+    buffers: Mutex<BTreeMap<u64, Weak<BufferHead>>>,
+}
+
+impl BufferCache {
+    fn buffers(&self) -> MutexGuard<'_, BTreeMap<u64, Weak<BufferHead>>> {
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Writes back every dirty buffer in use, returning the first error.
+    pub(crate) fn sync(&self) -> Result {
+        let live: Vec<_> = self.buffers().values().filter_map(Weak::upgrade).collect();
+
+        live.iter()
+            .map(|bh| bh.sync_dirty_buffer())
+            .fold(Ok(()), Result::and)
+    }
+}
+
+/// Sets the block size of a block-device filesystem, the equivalent of `sb_set_blocksize`.
+///
+/// Fails with `ENOTBLK` without a device and with `EINVAL` for sizes that are not a power of two
+/// between 512 and [`PAGE_SIZE`](crate::PAGE_SIZE). Returns the new size.
+pub fn sb_set_blocksize<T: FileSystem>(sb: &mut SuperBlock<T, New>, size: u32) -> Result<u32> {
+    if sb.device().is_none() {
+        return Err(ENOTBLK);
+    }
+
+    sb.set_blocksize(size)?;
+
+    // Buffers of the old size must not be handed out any more.
+    sb.buffers().buffers().clear();
+
+    Ok(size)
+}
+
+/// Reads block `block` of the device of `sb`, the equivalent of `sb_bread`.
+///
+/// Fails with `ENOTBLK` without a device and with `EIO` if the block is not entirely on it.
+pub fn sb_bread<T: FileSystem + ?Sized, S>(
+    sb: &SuperBlock<T, S>,
+    block: u64,
+) -> Result<ARef<BufferHead>> {
+    let device = sb.device().ok_or(ENOTBLK)?;
+    let mut buffers = sb.buffers().buffers();
+
+    if let Some(bh) = buffers.get(&block).and_then(Weak::upgrade) {
+        return Ok(bh);
+    }
+
+    let size = sb.blocksize() as usize;
+    let offset = block.checked_mul(size as u64).ok_or(EIO)?;
+    let mut data = vec![0; size].into_boxed_slice();
+
+    if device.read_at(&mut data, offset)? != size {
+        return Err(EIO);
+    }
+
+    let bh = Arc::new(BufferHead {
+        blocknr: block,
+        device: device.clone(),
+        data: Mutex::new(data),
+        dirty: AtomicBool::new(false),
+    });
+    buffers.insert(block, Arc::downgrade(&bh));
+
+    Ok(bh)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::MemDevice;
+    use crate::testing::TestFs;
+    use crate::types::code::EINVAL;
+
+    #[test]
+    fn buffers_are_shared_and_written_back() {
+        let dev = Arc::new(MemDevice::from_vec(
+            (0..4096).map(|i| (i / 1024) as u8).collect(),
+        ));
+        let mut sb = SuperBlock::<TestFs, New>::new(Some(dev.clone()));
+
+        assert_eq!(sb_set_blocksize(&mut sb, 1000), Err(EINVAL));
+        assert_eq!(sb_set_blocksize(&mut sb, 1024), Ok(1024));
+
+        let bh = sb_bread(&sb, 2).unwrap();
+        assert_eq!((bh.blocknr(), bh.size(), bh.data()[0]), (2, 1024, 2));
+        assert!(Arc::ptr_eq(&bh, &sb_bread(&sb, 2).unwrap()));
+        assert_eq!(sb_bread(&sb, 4).err(), Some(EIO));
+
+        bh.data()[0] = 0xff;
+        bh.mark_dirty();
+        assert_eq!(dev.contents()[2048], 2);
+        bh.sync_dirty_buffer().unwrap();
+        assert!(!bh.is_dirty());
+        assert_eq!(dev.contents()[2048], 0xff);
+
+        bh.data()[1] = 0xee;
+        bh.mark_dirty();
+        drop(bh);
+        assert_eq!(dev.contents()[2049], 0xee);
+
+        let mut sb = SuperBlock::<TestFs, New>::new(None);
+        assert_eq!(sb_set_blocksize(&mut sb, 1024), Err(ENOTBLK));
+        assert_eq!(sb_bread(&sb, 0).err(), Some(ENOTBLK));
+    }
+}
@@ -0,0 +1,103 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use crate::types::ARef;
+use crate::uapi::{gid_t, uid_t};
+
+/// The identity that operations run with, the equivalent of `struct cred`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    pub fsuid: uid_t,
+    pub fsgid: gid_t,
+    /// Supplementary groups.
+    pub groups: Vec<gid_t>,
+    /// Bypasses read, write and search permission checks, as `CAP_DAC_OVERRIDE` does.
+    pub dac_override: bool,
+}
+
+impl Credentials {
+    /// Root with every capability, which is what operations run with by default.
+    pub fn root() -> Self {
+        Self {
+            fsuid: 0,
+            fsgid: 0,
+            groups: Vec::new(),
+            dac_override: true,
+        }
+    }
+
+    /// An unprivileged user.
+    pub fn user(fsuid: uid_t, fsgid: gid_t) -> Self {
+        Self {
+            fsuid,
+            fsgid,
+            groups: Vec::new(),
+            dac_override: false,
+        }
+    }
+
+    pub fn with_groups(self, groups: Vec<gid_t>) -> Self {
+        Self { groups, ..self }
+    }
+
+    /// Whether `gid` is the filesystem group or one of the supplementary groups (`in_group_p`).
+    pub fn in_group(&self, gid: gid_t) -> bool {
+        self.fsgid == gid || self.groups.contains(&gid)
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<ARef<Credentials>>> = const { RefCell::new(None) };
+}
+
+/// The credentials of operations on this thread, the equivalent of `current_cred`.
+pub fn current() -> ARef<Credentials> {
+    CURRENT.with(|current| {
+        current
+            .borrow_mut()
+            .get_or_insert_with(|| Arc::new(Credentials::root()))
+            .clone()
+    })
+}
+
+/// Restores the credentials that [`override_creds`] replaced when dropped.
+#[must_use]
+pub struct Override {
+    old: Option<ARef<Credentials>>,
+}
+
+impl Drop for Override {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.old.take());
+    }
+}
+
+/// Runs the operations on this thread with `cred` until the returned guard is dropped.
+pub fn override_creds(cred: ARef<Credentials>) -> Override {
+    Override {
+        old: CURRENT.with(|current| current.borrow_mut().replace(cred)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_nest() {
+        assert_eq!(*current(), Credentials::root());
+
+        let user = Arc::new(Credentials::user(1000, 100).with_groups(vec![10]));
+        let outer = override_creds(user.clone());
+        assert!(current().in_group(10) && current().in_group(100));
+        assert!(!current().in_group(0));
+
+        let inner = override_creds(Arc::new(Credentials::user(1, 1)));
+        assert_eq!(current().fsuid, 1);
+        drop(inner);
+        assert_eq!(current(), user);
+
+        drop(outer);
+        assert_eq!(*current(), Credentials::root());
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::fs::FileSystem;
+use crate::inode::{self, INode, MAY_EXEC};
+use crate::types::code::{ENOENT, ENOTDIR};
+use crate::types::{ARef, Result};
+use crate::uapi;
+
+/// A directory entry: a name in a parent directory, bound to an inode or negative.
+///
+/// Children are cached in their parent keyed by name, and each entry keeps its parent alive, so
+/// subtrees are torn down explicitly with [`DEntry::d_drop`] and [`DEntry::shrink`].
+pub struct DEntry<T: FileSystem + ?Sized> {
+    name: Vec<u8>,
+    parent: Option<ARef<DEntry<T>>>,
+    inode: Mutex<Option<ARef<INode<T>>>>,
+    children: Mutex<BTreeMap<Vec<u8>, ARef<DEntry<T>>>>,
+}
+
+impl<T: FileSystem + ?Sized> DEntry<T> {
+    fn alloc(name: &[u8], parent: Option<ARef<Self>>, inode: Option<ARef<INode<T>>>) -> Self {
+        Self {
+            name: name.to_vec(),
+            parent,
+            inode: Mutex::new(inode),
+            children: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn name(&self) -> &[u8] {
+        &self.name
+    }
+
+    /// The parent entry; `None` for the root.
+    pub fn parent(&self) -> Option<&ARef<DEntry<T>>> {
+        self.parent.as_ref()
+    }
+
+    /// The inode this entry refers to; `None` for a negative entry.
+    pub fn inode(&self) -> Option<ARef<INode<T>>> {
+        self.inode.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.inode
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_none()
+    }
+
+    /// Binds a (negative) entry to `inode`, as `d_instantiate` does.
+    pub fn instantiate(&self, inode: ARef<INode<T>>) {
+        *self.inode.lock().unwrap_or_else(|e| e.into_inner()) = Some(inode);
+    }
+
+    /// Turns the entry negative, as `d_delete` does after an unlink.
+    pub fn d_delete(&self) {
+        self.inode.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+
+    /// Returns the cached child `name`, which may be negative.
+    pub fn cached(&self, name: &[u8]) -> Option<ARef<DEntry<T>>> {
+        self.children
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(name)
+            .cloned()
+    }
+
+    /// Adds a child entry to the cache, replacing any entry with the same name.
+    pub fn d_add(self: &ARef<Self>, name: &[u8], inode: Option<ARef<INode<T>>>) -> ARef<DEntry<T>> {
+        let child = ARef::new(Self::alloc(name, Some(self.clone()), inode));
+
+        let old = self
+            .children
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(name.to_vec(), child.clone());
+
+        if let Some(old) = old {
+            old.clear();
+        }
+
+        child
+    }
+
+    /// Removes the entry from its parent's cache, along with everything cached below it.
+    pub fn d_drop(&self) {
+        if let Some(parent) = &self.parent {
+            let mut children = parent.children.lock().unwrap_or_else(|e| e.into_inner());
+
+            if children
+                .get(&self.name)
+                .is_some_and(|c| core::ptr::eq(&**c, self))
+            {
+                children.remove(&self.name);
+            }
+        }
+
+        self.clear();
+    }
+
+    /// Drops every cached descendant.
+    fn clear(&self) {
+        let children =
+            core::mem::take(&mut *self.children.lock().unwrap_or_else(|e| e.into_inner()));
+
+        for child in children.values() {
+            child.clear();
+        }
+    }
+
+    /// Drops cached descendants that nobody else is using.
+    pub fn shrink(&self) {
+        let mut children = self.children.lock().unwrap_or_else(|e| e.into_inner());
+
+        for child in children.values() {
+            child.shrink();
+        }
+
+        children.retain(|_, child| {
+            ARef::strong_count(child) > 1
+                || !child
+                    .children
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .is_empty()
+        });
+    }
+}
+
+/// A dentry that is being looked up and is not in the cache yet.
+pub struct Unhashed<'a, T: FileSystem + ?Sized> {
+    parent: &'a ARef<DEntry<T>>,
+    name: &'a [u8],
+}
+
+impl<'a, T: FileSystem + ?Sized> Unhashed<'a, T> {
+    pub(crate) fn new(parent: &'a ARef<DEntry<T>>, name: &'a [u8]) -> Self {
+        Self { parent, name }
+    }
+
+    pub fn name(&self) -> &[u8] {
+        self.name
+    }
+
+    /// Adds the entry to the cache, bound to `inode` or negative if `None`.
+    ///
+    /// Returns `None` when the entry itself was used, as `d_splice_alias` does.
+    pub fn splice_alias(self, inode: Option<ARef<INode<T>>>) -> Result<Option<ARef<DEntry<T>>>> {
+        self.parent.d_add(self.name, inode);
+        Ok(None)
+    }
+}
+
+/// The root entry of a mounted filesystem.
+pub struct Root<T: FileSystem + ?Sized>(ARef<DEntry<T>>);
+
+impl<T: FileSystem + ?Sized> Root<T> {
+    pub fn try_new(inode: ARef<INode<T>>) -> Result<Self> {
+        if !uapi::s_isdir(inode.mode()) {
+            return Err(ENOTDIR);
+        }
+
+        Ok(Self(ARef::new(DEntry::alloc(b"/", None, Some(inode)))))
+    }
+
+    pub fn dentry(&self) -> &ARef<DEntry<T>> {
+        &self.0
+    }
+}
+
+impl<T: FileSystem + ?Sized> Drop for Root<T> {
+    fn drop(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Returns the child `name` of `parent`, asking the filesystem on a cache miss.
+///
+/// The result may be a negative entry. Fails with `EACCES` if the current credentials may not
+/// search `parent`, whether or not the child is cached.
+pub fn lookup_one<T: FileSystem + ?Sized>(
+    parent: &ARef<DEntry<T>>,
+    name: &[u8],
+) -> Result<ARef<DEntry<T>>> {
+    match name {
+        b"." => return Ok(parent.clone()),
+        b".." => return Ok(parent.parent().unwrap_or(parent).clone()),
+        _ => {}
+    }
+
+    let dir = parent.inode().ok_or(ENOENT)?;
+    if !uapi::s_isdir(dir.mode()) {
+        return Err(ENOTDIR);
+    }
+
+    inode::generic_permission(&dir, MAY_EXEC)?;
+
+    if let Some(dentry) = parent.cached(name) {
+        return Ok(dentry);
+    }
+
+    let locked = dir.lock_shared();
+    if let Some(alias) = dir.ops().lookup(&locked, Unhashed::new(parent, name))? {
+        return Ok(alias);
+    }
+
+    // A filesystem that did not splice anything found nothing.
+    Ok(parent
+        .cached(name)
+        .unwrap_or_else(|| parent.d_add(name, None)))
+}
+
+/// Resolves a `/`-separated `path` relative to `start`.
+///
+/// Every component but the last must be an existing directory; the last may be negative.
+pub fn walk<T: FileSystem + ?Sized>(
+    start: &ARef<DEntry<T>>,
+    path: &[u8],
+) -> Result<ARef<DEntry<T>>> {
+    let mut dentry = start.clone();
+
+    for name in path.split(|&b| b == b'/').filter(|c| !c.is_empty()) {
+        if dentry.is_negative() {
+            return Err(ENOENT);
+        }
+
+        dentry = lookup_one(&dentry, name)?;
+    }
+
+    Ok(dentry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{get_inode, super_block};
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn lookups_are_cached_including_negative() {
+        let sb = super_block();
+        let root = Root::try_new(get_inode(&sb, 1, uapi::S_IFDIR | 0o755)).unwrap();
+
+        let file = walk(root.dentry(), b"/dir/file").unwrap();
+        assert_eq!(file.name(), b"file");
+        assert!(!file.is_negative());
+        assert_eq!(sb.data().load(Ordering::Relaxed), 2);
+
+        let missing = walk(root.dentry(), b"dir/missing").unwrap();
+        assert!(missing.is_negative());
+        assert_eq!(sb.data().load(Ordering::Relaxed), 3);
+
+        walk(root.dentry(), b"dir/./missing").unwrap();
+        let again = walk(root.dentry(), b"dir/../dir/file").unwrap();
+        assert!(ARef::ptr_eq(&file, &again));
+        assert_eq!(sb.data().load(Ordering::Relaxed), 3);
+
+        assert_eq!(walk(root.dentry(), b"dir/missing/x").err(), Some(ENOENT));
+        assert_eq!(walk(root.dentry(), b"dir/file/x").err(), Some(ENOTDIR));
+    }
+
+    #[test]
+    fn shrink_and_drop_release_inodes() {
+        let sb = super_block();
+        let root = Root::try_new(get_inode(&sb, 1, uapi::S_IFDIR | 0o755)).unwrap();
+
+        let file = walk(root.dentry(), b"dir/file").unwrap();
+        assert_eq!(sb.cached_inodes(), 3);
+
+        // `file` is still in use, so its parent must stay too.
+        root.dentry().shrink();
+        assert_eq!(sb.cached_inodes(), 3);
+
+        file.d_drop();
+        drop(file);
+        assert_eq!(sb.cached_inodes(), 2);
+
+        root.dentry().shrink();
+        assert_eq!(sb.cached_inodes(), 1);
+
+        drop(root);
+        assert_eq!(sb.cached_inodes(), 0);
+    }
+}
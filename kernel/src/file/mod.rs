@@ -0,0 +1,403 @@
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+use crate::cred::{self, Credentials};
+use crate::dentry::DEntry;
+use crate::fs::{FileSystem, Offset};
+use crate::inode::{INode, ReadSem};
+use crate::types::code::{EINVAL, ENOENT, ENOTDIR, ENXIO, EOVERFLOW};
+use crate::types::{ARef, Locked, Result};
+use crate::uapi::{self, O_RDWR, S_IFMT, mode_t};
+use crate::user;
+
+/// An open file.
+pub struct File<T: FileSystem + ?Sized> {
+    inode: ARef<INode<T>>,
+    dentry: Option<ARef<DEntry<T>>>,
+    /// The `O_*` flags the file was opened with.
+    flags: u32,
+    /// The credentials of the opener (`f_cred`).
+    cred: ARef<Credentials>,
+    pos: Mutex<Offset>,
+}
+
+impl<T: FileSystem + ?Sized> File<T> {
+    /// Opens `inode` without a path, for reading and writing.
+    pub fn new(inode: ARef<INode<T>>) -> Self {
+        Self {
+            inode,
+            dentry: None,
+            flags: O_RDWR,
+            cred: cred::current(),
+            pos: Mutex::new(0),
+        }
+    }
+
+    /// Opens the inode that `dentry` refers to, for reading and writing.
+    pub fn open(dentry: ARef<DEntry<T>>) -> Result<Self> {
+        Ok(Self {
+            inode: dentry.inode().ok_or(ENOENT)?,
+            dentry: Some(dentry),
+            flags: O_RDWR,
+            cred: cred::current(),
+            pos: Mutex::new(0),
+        })
+    }
+
+    /// Replaces the `O_*` flags the file was opened with.
+    pub fn with_flags(mut self, flags: u32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn cred(&self) -> &ARef<Credentials> {
+        &self.cred
+    }
+
+    pub fn inode(&self) -> &INode<T> {
+        &self.inode
+    }
+
+    pub fn dentry(&self) -> Option<&ARef<DEntry<T>>> {
+        self.dentry.as_ref()
+    }
+
+    /// The current file position (`f_pos`).
+    pub fn pos(&self) -> Offset {
+        *self.pos.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn set_pos(&self, pos: Offset) {
+        *self.pos.lock().unwrap_or_else(|e| e.into_inner()) = pos;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Whence {
+    Set,
+    Cur,
+    End,
+    Data,
+    Hole,
+}
+
+/// Moves the position of `file` as `lseek` does for a file with no holes.
+pub fn generic_seek<T: FileSystem + ?Sized>(
+    file: &File<T>,
+    offset: Offset,
+    whence: Whence,
+) -> Result<Offset> {
+    let size: Offset = file.inode().size().try_into().map_err(|_| EOVERFLOW)?;
+
+    let new_pos = match whence {
+        Whence::Set => offset,
+        Whence::Cur => file.pos().checked_add(offset).ok_or(EINVAL)?,
+        Whence::End => size.checked_add(offset).ok_or(EINVAL)?,
+        Whence::Data if offset >= size => return Err(ENXIO),
+        Whence::Data => offset,
+        Whence::Hole if offset >= size => return Err(ENXIO),
+        Whence::Hole => size,
+    };
+
+    if new_pos < 0 {
+        return Err(EINVAL);
+    }
+
+    file.set_pos(new_pos);
+    Ok(new_pos)
+}
+
+/// An entry produced by [`DirEmitter::emit`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: Vec<u8>,
+    pub ino: u64,
+    pub etype: DirEntryType,
+    /// Position of the next entry.
+    pub pos: Offset,
+}
+
+/// Collects directory entries for `getdents`, up to a byte budget.
+pub struct DirEmitter {
+    pos: Offset,
+    space: usize,
+    entries: Vec<DirEntry>,
+}
+
+impl DirEmitter {
+    /// Size of a `linux_dirent64` record holding `name`.
+    fn reclen(name: &[u8]) -> usize {
+        (19 + name.len() + 1).next_multiple_of(8)
+    }
+
+    /// Creates an emitter that starts at `pos` and accepts entries worth `space` bytes of
+    /// `linux_dirent64` records.
+    pub fn new(pos: Offset, space: usize) -> Self {
+        Self {
+            pos,
+            space,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn pos(&self) -> Offset {
+        self.pos
+    }
+
+    pub fn entries(&self) -> &[DirEntry] {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<DirEntry> {
+        self.entries
+    }
+
+    /// Emits an entry and advances the position by `pos_inc`.
+    ///
+    /// Returns `false` without emitting anything once the buffer is full, in which case the
+    /// caller must stop and report success; iteration resumes from [`DirEmitter::pos`].
+    pub fn emit(&mut self, pos_inc: Offset, name: &[u8], ino: u64, etype: DirEntryType) -> bool {
+        let reclen = Self::reclen(name);
+
+        if reclen > self.space {
+            return false;
+        }
+
+        let Some(pos) = self.pos.checked_add(pos_inc) else {
+            return false;
+        };
+
+        self.space -= reclen;
+        self.pos = pos;
+        self.entries.push(DirEntry {
+            name: name.to_vec(),
+            ino,
+            etype,
+            pos,
+        });
+
+        true
+    }
+
+    /// Emits `.` and `..` if the position is still before them.
+    pub fn emit_dots<T: FileSystem + ?Sized>(&mut self, file: &File<T>) -> bool {
+        let ino = file.inode().ino() as u64;
+
+        // Files opened without a path, and the root, are their own parent.
+        let parent_ino = file
+            .dentry()
+            .and_then(|d| d.parent())
+            .and_then(|p| p.inode())
+            .map_or(ino, |p| p.ino() as u64);
+
+        if self.pos == 0 && !self.emit(1, b".", ino, DirEntryType::Dir) {
+            return false;
+        }
+
+        if self.pos == 1 && !self.emit(1, b"..", parent_ino, DirEntryType::Dir) {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// The file type reported with a directory entry, the `DT_*` values of `linux_dirent64`.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirEntryType {
+    Unknown = 0,
+    Fifo = 1,
+    Chr = 2,
+    Dir = 4,
+    Blk = 6,
+    Reg = 8,
+    Lnk = 10,
+    Sock = 12,
+    Wht = 14,
+}
+
+impl DirEntryType {
+    /// The entry type of a file with mode `mode`.
+    pub fn from_mode(mode: impl Into<mode_t>) -> Self {
+        match mode.into() & S_IFMT {
+            uapi::S_IFIFO => Self::Fifo,
+            uapi::S_IFCHR => Self::Chr,
+            uapi::S_IFDIR => Self::Dir,
+            uapi::S_IFBLK => Self::Blk,
+            uapi::S_IFREG => Self::Reg,
+            uapi::S_IFLNK => Self::Lnk,
+            uapi::S_IFSOCK => Self::Sock,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl From<DirEntryType> for u8 {
+    fn from(etype: DirEntryType) -> Self {
+        etype as u8
+    }
+}
+
+/// File operations, the equivalent of `struct file_operations`.
+pub trait Operations {
+    type FileSystem: FileSystem + ?Sized;
+
+    fn seek(_file: &File<Self::FileSystem>, _offset: Offset, _whence: Whence) -> Result<Offset> {
+        Err(EINVAL)
+    }
+
+    fn read(
+        _file: &File<Self::FileSystem>,
+        _writer: &mut user::Writer<'_>,
+        _offset: &mut Offset,
+    ) -> Result<usize> {
+        Err(EINVAL)
+    }
+
+    fn write(
+        _file: &File<Self::FileSystem>,
+        _reader: &mut user::Reader<'_>,
+        _offset: &mut Offset,
+    ) -> Result<usize> {
+        Err(EINVAL)
+    }
+
+    fn read_dir(
+        _file: &File<Self::FileSystem>,
+        _inode: &Locked<&INode<Self::FileSystem>, ReadSem>,
+        _emitter: &mut DirEmitter,
+    ) -> Result {
+        Err(ENOTDIR)
+    }
+}
+
+/// A table of file operations, created from an [`Operations`] implementation.
+pub struct Ops<T: FileSystem + ?Sized>(&'static Table<T>);
+
+impl<T: FileSystem + ?Sized> Clone for Ops<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: FileSystem + ?Sized> Copy for Ops<T> {}
+
+#[allow(clippy::type_complexity)]
+struct Table<T: FileSystem + ?Sized> {
+    seek: fn(&File<T>, Offset, Whence) -> Result<Offset>,
+    read: fn(&File<T>, &mut user::Writer<'_>, &mut Offset) -> Result<usize>,
+    write: fn(&File<T>, &mut user::Reader<'_>, &mut Offset) -> Result<usize>,
+    read_dir: fn(&File<T>, &Locked<&INode<T>, ReadSem>, &mut DirEmitter) -> Result,
+}
+
+struct TableFor<U: ?Sized>(PhantomData<U>);
+
+impl<U: Operations + ?Sized> TableFor<U> {
+    const TABLE: Table<U::FileSystem> = Table {
+        seek: U::seek,
+        read: U::read,
+        write: U::write,
+        read_dir: U::read_dir,
+    };
+}
+
+/// Operations for files whose inode was not given any.
+struct NoOps<T: ?Sized>(PhantomData<T>);
+
+impl<T: FileSystem + ?Sized> Operations for NoOps<T> {
+    type FileSystem = T;
+}
+
+impl<T: FileSystem + ?Sized> Ops<T> {
+    pub const fn new<U: Operations<FileSystem = T> + ?Sized>() -> Self {
+        Self(&TableFor::<U>::TABLE)
+    }
+
+    pub const fn empty() -> Self {
+        Self::new::<NoOps<T>>()
+    }
+
+    pub fn seek(&self, file: &File<T>, offset: Offset, whence: Whence) -> Result<Offset> {
+        (self.0.seek)(file, offset, whence)
+    }
+
+    pub fn read(
+        &self,
+        file: &File<T>,
+        writer: &mut user::Writer<'_>,
+        offset: &mut Offset,
+    ) -> Result<usize> {
+        (self.0.read)(file, writer, offset)
+    }
+
+    pub fn write(
+        &self,
+        file: &File<T>,
+        reader: &mut user::Reader<'_>,
+        offset: &mut Offset,
+    ) -> Result<usize> {
+        (self.0.write)(file, reader, offset)
+    }
+
+    pub fn read_dir(
+        &self,
+        file: &File<T>,
+        inode: &Locked<&INode<T>, ReadSem>,
+        emitter: &mut DirEmitter,
+    ) -> Result {
+        (self.0.read_dir)(file, inode, emitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{new_inode, super_block};
+
+    #[test]
+    fn generic_seek_whence() {
+        let sb = super_block();
+        let inode = new_inode(&sb, 1);
+        inode.set_size(100);
+        let file = File::new(inode);
+
+        assert_eq!(generic_seek(&file, 10, Whence::Set), Ok(10));
+        assert_eq!(generic_seek(&file, 5, Whence::Cur), Ok(15));
+        assert_eq!(generic_seek(&file, -1, Whence::End), Ok(99));
+        assert_eq!(generic_seek(&file, 20, Whence::Hole), Ok(100));
+        assert_eq!(generic_seek(&file, 100, Whence::Data), Err(ENXIO));
+        assert_eq!(generic_seek(&file, -200, Whence::Cur), Err(EINVAL));
+        assert_eq!(file.pos(), 100);
+    }
+
+    #[test]
+    fn dir_emitter_back_pressure() {
+        let sb = super_block();
+        let file = File::new(new_inode(&sb, 1));
+
+        // Room for the two dots (24 bytes each) and nothing else.
+        let mut emitter = DirEmitter::new(0, 50);
+        assert!(emitter.emit_dots(&file));
+        assert!(!emitter.emit(8, b"file", 2, DirEntryType::Reg));
+        assert_eq!(emitter.pos(), 2);
+
+        let mut emitter = DirEmitter::new(emitter.pos(), 50);
+        assert!(emitter.emit_dots(&file));
+        assert!(emitter.emit(8, b"file", 2, DirEntryType::Reg));
+        assert_eq!(emitter.entries()[0].pos, 10);
+    }
+
+    #[test]
+    fn dir_entry_type_from_mode() {
+        assert_eq!(DirEntryType::from_mode(0o040755u32), DirEntryType::Dir);
+        assert_eq!(DirEntryType::from_mode(0o100644u16), DirEntryType::Reg);
+        assert_eq!(DirEntryType::from_mode(uapi::S_IFLNK), DirEntryType::Lnk);
+        assert_eq!(DirEntryType::from_mode(0u32), DirEntryType::Unknown);
+        assert_eq!(u8::from(DirEntryType::Dir), 4);
+    }
+}
@@ -0,0 +1,267 @@
+//! Mount option parsing, the equivalent of `struct fs_context` and `fs_parser`.
+
+use crate::fs::FileSystem;
+use crate::sb::{SB_NOATIME, SB_RDONLY};
+use crate::types::code::EINVAL;
+use crate::types::{Error, Result};
+
+/// The kind of value a mount parameter takes.
+#[derive(Clone, Copy, Debug)]
+pub enum ParamType {
+    /// No value, as in `noatime`.
+    Flag,
+    /// A decimal number, as in `uid=1000`.
+    U32,
+    /// Any non-empty string.
+    String,
+    /// One of a fixed set of names, each mapped to a number.
+    Enum(&'static [(&'static str, u32)]),
+}
+
+/// A mount parameter that a filesystem accepts, the equivalent of `struct fs_parameter_spec`.
+#[derive(Clone, Copy, Debug)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    /// Filesystem-chosen number identifying the parameter in [`Param::opt`].
+    pub opt: u32,
+    pub kind: ParamType,
+}
+
+impl ParamSpec {
+    pub const fn flag(name: &'static str, opt: u32) -> Self {
+        Self {
+            name,
+            opt,
+            kind: ParamType::Flag,
+        }
+    }
+
+    pub const fn u32(name: &'static str, opt: u32) -> Self {
+        Self {
+            name,
+            opt,
+            kind: ParamType::U32,
+        }
+    }
+
+    pub const fn string(name: &'static str, opt: u32) -> Self {
+        Self {
+            name,
+            opt,
+            kind: ParamType::String,
+        }
+    }
+
+    pub const fn enumeration(
+        name: &'static str,
+        opt: u32,
+        values: &'static [(&'static str, u32)],
+    ) -> Self {
+        Self {
+            name,
+            opt,
+            kind: ParamType::Enum(values),
+        }
+    }
+}
+
+/// The value of a parsed parameter, matching its [`ParamType`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParamValue {
+    Flag,
+    U32(u32),
+    String(String),
+    Enum(u32),
+}
+
+/// A parameter that matched a [`ParamSpec`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Param {
+    pub opt: u32,
+    pub value: ParamValue,
+}
+
+/// Mount options on their way to [`FileSystem::fill_super`] or
+/// [`sb::Operations::remount_fs`].
+///
+/// The generic options `ro`, `rw`, `noatime` and `atime` become superblock flags; everything else
+/// must match one of [`FileSystem::PARAMETERS`].
+///
+/// [`sb::Operations::remount_fs`]: crate::sb::Operations::remount_fs
+#[derive(Debug, Default)]
+pub struct Context {
+    sb_flags: u32,
+    params: Vec<Param>,
+    /// Why parsing failed, the equivalent of the `fs_context` log.
+    error: Option<String>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `SB_*` flags requested by the generic options.
+    pub fn sb_flags(&self) -> u32 {
+        self.sb_flags
+    }
+
+    /// The filesystem parameters, in the order they were given.
+    pub fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    /// The message explaining the last `EINVAL` returned while parsing.
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Records `msg` as the reason parsing failed and returns `EINVAL`, like `invalf`.
+    fn invalf(&mut self, msg: String) -> Error {
+        self.error = Some(msg);
+        EINVAL
+    }
+
+    /// Parses a comma-separated option string such as `ro,uid=1000`, the equivalent of
+    /// `generic_parse_monolithic`.
+    pub fn parse_monolithic<T: FileSystem + ?Sized>(&mut self, data: &str) -> Result {
+        for option in data.split(',').filter(|option| !option.is_empty()) {
+            let (key, value) = match option.split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (option, None),
+            };
+
+            self.parse_param::<T>(key, value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Parses one `key` or `key=value` option, the equivalent of `vfs_parse_fs_param`.
+    pub fn parse_param<T: FileSystem + ?Sized>(
+        &mut self,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result {
+        let flag = match key {
+            "ro" | "rw" => SB_RDONLY,
+            "noatime" | "atime" => SB_NOATIME,
+            _ => 0,
+        };
+
+        if flag != 0 {
+            if value.is_some() {
+                return Err(self.invalf(format!("{}: {key} takes no value", T::NAME)));
+            }
+
+            match key {
+                "ro" | "noatime" => self.sb_flags |= flag,
+                _ => self.sb_flags &= !flag,
+            }
+
+            return Ok(());
+        }
+
+        let Some(spec) = T::PARAMETERS.iter().find(|spec| spec.name == key) else {
+            return Err(self.invalf(format!("{}: Unknown parameter '{key}'", T::NAME)));
+        };
+
+        let value = match (spec.kind, value) {
+            (ParamType::Flag, None) => ParamValue::Flag,
+            (ParamType::U32, Some(value)) => match value.parse() {
+                Ok(n) => ParamValue::U32(n),
+                Err(_) => {
+                    return Err(self.invalf(format!(
+                        "{}: Bad value for '{key}': expected a number",
+                        T::NAME
+                    )));
+                }
+            },
+            (ParamType::String, Some(value)) if !value.is_empty() => {
+                ParamValue::String(value.into())
+            }
+            (ParamType::Enum(values), Some(value)) => {
+                match values.iter().find(|(name, _)| *name == value) {
+                    Some(&(_, n)) => ParamValue::Enum(n),
+                    None => {
+                        return Err(
+                            self.invalf(format!("{}: Bad value for '{key}': {value}", T::NAME))
+                        );
+                    }
+                }
+            }
+            (ParamType::Flag, Some(_)) => {
+                return Err(self.invalf(format!("{}: {key} takes no value", T::NAME)));
+            }
+            _ => {
+                return Err(self.invalf(format!("{}: {key} requires a value", T::NAME)));
+            }
+        };
+
+        self.params.push(Param {
+            opt: spec.opt,
+            value,
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{OPT_DEBUG, OPT_MODE, OPT_NAME, OPT_SIZE, TestFs};
+
+    fn parse(data: &str) -> (Result, Context) {
+        let mut ctx = Context::new();
+        let res = ctx.parse_monolithic::<TestFs>(data);
+
+        (res, ctx)
+    }
+
+    #[test]
+    fn parses_generic_and_filesystem_options() {
+        let (res, ctx) = parse("ro,size=12,,debug,mode=fast,noatime,name=x,rw");
+        res.unwrap();
+
+        assert_eq!(ctx.sb_flags(), SB_NOATIME);
+        assert_eq!(
+            ctx.params(),
+            [
+                Param {
+                    opt: OPT_SIZE,
+                    value: ParamValue::U32(12)
+                },
+                Param {
+                    opt: OPT_DEBUG,
+                    value: ParamValue::Flag
+                },
+                Param {
+                    opt: OPT_MODE,
+                    value: ParamValue::Enum(1)
+                },
+                Param {
+                    opt: OPT_NAME,
+                    value: ParamValue::String("x".into())
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_options() {
+        for (data, msg) in [
+            ("bogus", "testfs: Unknown parameter 'bogus'"),
+            ("size=-1", "testfs: Bad value for 'size': expected a number"),
+            ("size", "testfs: size requires a value"),
+            ("debug=1", "testfs: debug takes no value"),
+            ("mode=slow", "testfs: Bad value for 'mode': slow"),
+            ("name=", "testfs: name requires a value"),
+            ("ro=1", "testfs: ro takes no value"),
+        ] {
+            let (res, ctx) = parse(data);
+            assert_eq!(res, Err(EINVAL), "{data}");
+            assert_eq!(ctx.error(), Some(msg));
+        }
+    }
+}
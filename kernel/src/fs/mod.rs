@@ -1,23 +1,272 @@
+use std::collections::BTreeSet;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+use crate::block::BlockDevice;
+use crate::dentry;
 use crate::inode;
 use crate::sb::{self, SuperBlock};
+#[cfg(kani)]
+use crate::types::ARef;
 use crate::types::Result;
+use crate::types::code::{EBUSY, ENOTBLK};
+use crate::writeback;
+
+pub mod context;
 
-pub struct File;
+pub use context::{Context, Param, ParamSpec, ParamType, ParamValue};
 
-pub trait FileSystem {
+pub trait FileSystem: 'static {
     type Data: Send + Sync;
 
+    #[cfg(not(kani))]
     type INodeData: Send + Sync;
 
+    /// Under Kani, inode data must be arbitrary so that cached inodes can be modelled.
+    #[cfg(kani)]
+    type INodeData: Send + Sync + kani::Arbitrary;
+
     const NAME: &str;
     const SUPER_TYPE: sb::Type = sb::Type::Independent;
 
+    /// Mount options accepted on top of the generic ones, the equivalent of `parameters` in
+    /// `struct file_system_type`.
+    const PARAMETERS: &'static [ParamSpec] = &[];
+
     fn fill_super(
         sb: &mut SuperBlock<Self, sb::New>,
+        ctx: &Context,
         mapper: Option<inode::Mapper<Self>>,
     ) -> Result<Self::Data>;
 
-    // fn init_root(sb: &SuperBlock<Self>) -> Result<dentry::Root<Self>>;
+    fn init_root(sb: &SuperBlock<Self>) -> Result<dentry::Root<Self>>;
+
+    /// Initialises `new` as an arbitrary inode that Kani treats as already cached.
+    ///
+    /// [`SuperBlock::get_or_create_inode`] calls this on the nondeterministic path where the
+    /// inode exists. Filesystems override it to derive the attributes and operations from the
+    /// arbitrary data, as they would when reading the inode from disk.
+    #[cfg(kani)]
+    fn kani_existing_inode(new: inode::New<Self>) -> Result<ARef<inode::INode<Self>>>
+    where
+        Self: Sized,
+    {
+        new.init(inode::Params {
+            mode: kani::any(),
+            size: kani::any(),
+            blocks: kani::any(),
+            nlink: kani::any(),
+            uid: kani::any(),
+            gid: kani::any(),
+            atime: kani::any(),
+            mtime: kani::any(),
+            ctime: kani::any(),
+            value: kani::any(),
+        })
+    }
 }
 
 pub type Offset = i64;
+
+/// Names of the registered filesystem types.
+static REGISTERED: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+/// A registered filesystem type; it is unregistered when dropped.
+pub struct Registration<T: FileSystem + ?Sized> {
+    _p: PhantomData<T>,
+}
+
+impl<T: FileSystem + ?Sized> Registration<T> {
+    /// Registers `T`, failing with `EBUSY` if a filesystem with the same name is registered.
+    pub fn new() -> Result<Self> {
+        let mut registered = REGISTERED.lock()?;
+
+        if !registered.insert(T::NAME) {
+            return Err(EBUSY);
+        }
+
+        Ok(Self { _p: PhantomData })
+    }
+
+    pub fn mount(&self, device: Option<Arc<dyn BlockDevice>>, data: &str) -> Result<SuperBlock<T>>
+    where
+        T: Sized,
+    {
+        mount(device, data)
+    }
+
+    pub fn unmount(&self, sb: SuperBlock<T>) -> Result
+    where
+        T: Sized,
+    {
+        kill_sb(sb)
+    }
+}
+
+impl<T: FileSystem + ?Sized> Drop for Registration<T> {
+    fn drop(&mut self) {
+        if let Ok(mut registered) = REGISTERED.lock() {
+            registered.remove(T::NAME);
+        }
+    }
+}
+
+/// Mounts a `T` filesystem from `device` with the comma-separated options in `data`.
+///
+/// Options are parsed into a [`Context`]; see [`get_tree`].
+pub fn mount<T: FileSystem>(
+    device: Option<Arc<dyn BlockDevice>>,
+    data: &str,
+) -> Result<SuperBlock<T>> {
+    let mut ctx = Context::new();
+    ctx.parse_monolithic::<T>(data)?;
+
+    get_tree(device, &ctx)
+}
+
+/// Mounts a `T` filesystem from `device` with the options in `ctx`, the equivalent of
+/// `vfs_get_tree`.
+///
+/// Block-device filesystems require a device and fail with `ENOTBLK` without one. The returned
+/// superblock must be released with [`kill_sb`]; if the root cannot be set up, the superblock is
+/// put before failing.
+pub fn get_tree<T: FileSystem>(
+    device: Option<Arc<dyn BlockDevice>>,
+    ctx: &Context,
+) -> Result<SuperBlock<T>> {
+    if matches!(T::SUPER_TYPE, sb::Type::BlockDev) && device.is_none() {
+        return Err(ENOTBLK);
+    }
+
+    let mapper = device.clone().map(inode::Mapper::for_device).transpose()?;
+
+    let mut sb = SuperBlock::new(device);
+    sb.set_flags(ctx.sb_flags());
+    let data = T::fill_super(&mut sb, ctx, mapper)?;
+    let sb = sb.ready(data);
+
+    let root = match T::init_root(&sb) {
+        Ok(root) => root,
+        Err(e) => {
+            sb.ops().put_super(&sb);
+            return Err(e);
+        }
+    };
+    sb.set_root(Some(root));
+
+    Ok(sb)
+}
+
+/// Tears down a mounted superblock: drops the dentry tree, and with it the cached inodes, writes
+/// back whatever is still dirty with [`writeback::sync_filesystem`], then calls
+/// [`sb::Operations::put_super`].
+pub fn kill_sb<T: FileSystem>(sb: SuperBlock<T>) -> Result {
+    drop(sb.set_root(None));
+
+    writeback::sync_filesystem(&sb)?;
+    sb.ops().put_super(&sb);
+
+    Ok(())
+}
+
+/// Applies the comma-separated options in `data` to a mounted filesystem, after writing back
+/// what is dirty.
+pub fn remount<T: FileSystem>(sb: &SuperBlock<T>, data: &str) -> Result {
+    let mut ctx = Context::new();
+    ctx.parse_monolithic::<T>(data)?;
+
+    writeback::sync_filesystem(sb)?;
+    sb.ops().remount_fs(sb, &ctx)?;
+    sb.update_flags(ctx.sb_flags());
+
+    Ok(())
+}
+
+/// Reports the statistics of a mounted filesystem, the equivalent of `vfs_statfs`.
+pub fn statfs<T: FileSystem>(sb: &SuperBlock<T>) -> Result<sb::StatFs> {
+    sb.ops().statfs(sb)
+}
+
+/// The mount options of a mounted filesystem as they appear in `/proc/mounts`: the generic ones
+/// followed by those from [`sb::Operations::show_options`].
+pub fn show_options<T: FileSystem>(sb: &SuperBlock<T>) -> Result<String> {
+    let mut out = String::from(if sb.is_rdonly() { "ro" } else { "rw" });
+
+    if sb.flags() & sb::SB_NOATIME != 0 {
+        out.push_str(",noatime");
+    }
+
+    sb.ops().show_options(sb, &mut out)?;
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::testing::TestFs;
+    use crate::types::code::EIO;
+
+    /// A filesystem whose root cannot be read.
+    struct NoRoot;
+
+    thread_local! {
+        static PUT_SUPERS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    impl FileSystem for NoRoot {
+        type Data = ();
+        type INodeData = ();
+        const NAME: &str = "noroot";
+
+        fn fill_super(
+            sb: &mut SuperBlock<Self, sb::New>,
+            _: &Context,
+            _: Option<inode::Mapper<Self>>,
+        ) -> Result {
+            sb.set_ops(sb::Ops::new::<NoRoot>());
+            Ok(())
+        }
+
+        fn init_root(_: &SuperBlock<Self>) -> Result<dentry::Root<Self>> {
+            Err(EIO)
+        }
+    }
+
+    impl sb::Operations for NoRoot {
+        type FileSystem = Self;
+
+        fn put_super(_: &SuperBlock<Self>) {
+            PUT_SUPERS.with(|n| n.set(n.get() + 1));
+        }
+    }
+
+    #[test]
+    fn registration_is_exclusive() {
+        let reg = Registration::<TestFs>::new().unwrap();
+        assert!(Registration::<TestFs>::new().is_err());
+
+        let sb = reg.mount(None, "").unwrap();
+        let root = sb.root().unwrap();
+        assert_eq!(root.inode().unwrap().ino(), 1);
+
+        dentry::walk(&root, b"dir/file").unwrap();
+        drop(root);
+        assert_eq!(sb.cached_inodes(), 3);
+
+        reg.unmount(sb.handle()).unwrap();
+        assert!(sb.root().is_none());
+        assert_eq!(sb.cached_inodes(), 0);
+
+        drop(reg);
+        drop(Registration::<TestFs>::new().unwrap());
+    }
+
+    #[test]
+    fn failed_mounts_put_the_superblock() {
+        assert_eq!(get_tree::<NoRoot>(None, &Context::new()).err(), Some(EIO));
+        assert_eq!(PUT_SUPERS.with(Cell::get), 1);
+    }
+}
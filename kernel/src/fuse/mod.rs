@@ -0,0 +1,769 @@
+//! Serving a mounted filesystem to the host kernel over the FUSE protocol, through `/dev/fuse`.
+//!
+//! Requests are translated into calls on a [`Vfs`], so the filesystem runs the same inode and file
+//! operations as it does under the model. Only protocol 7.23 and later is spoken.
+//!
+//! Node ids are inode numbers, so a number freed and reused while the kernel still holds its node
+//! comes back with a higher generation, and the kernel drops its stale inode. Numbers reused after
+//! the node was forgotten keep their generation, as the filesystem does not store one.
+
+use std::collections::BTreeMap;
+use std::ffi::{CString, c_char, c_int, c_ulong, c_void};
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::PAGE_SIZE;
+use crate::cred::{self, Credentials};
+use crate::dentry::DEntry;
+use crate::file::File;
+use crate::fs::{self, FileSystem, Offset};
+use crate::inode::{Attr, Stat};
+use crate::time::Timespec;
+use crate::types::code::{EAGAIN, EBADF, EINTR, EINVAL, ENODEV, ENOENT, ENOSYS};
+use crate::types::{ARef, Error, Result};
+use crate::uapi::{O_DIRECTORY, O_RDONLY, S_IFDIR};
+use crate::vfs::Vfs;
+use crate::writeback;
+
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+const FUSE_ROOT_ID: u64 = 1;
+
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_SETATTR: u32 = 4;
+const FUSE_MKDIR: u32 = 9;
+const FUSE_UNLINK: u32 = 10;
+const FUSE_RMDIR: u32 = 11;
+const FUSE_RENAME: u32 = 12;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_WRITE: u32 = 16;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_FSYNC: u32 = 20;
+const FUSE_FLUSH: u32 = 25;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_FSYNCDIR: u32 = 30;
+const FUSE_ACCESS: u32 = 34;
+const FUSE_CREATE: u32 = 35;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+const FUSE_RENAME2: u32 = 45;
+
+const FUSE_BIG_WRITES: u32 = 1 << 5;
+
+const FATTR_MODE: u32 = 1 << 0;
+const FATTR_UID: u32 = 1 << 1;
+const FATTR_GID: u32 = 1 << 2;
+const FATTR_SIZE: u32 = 1 << 3;
+const FATTR_ATIME: u32 = 1 << 4;
+const FATTR_MTIME: u32 = 1 << 5;
+const FATTR_ATIME_NOW: u32 = 1 << 7;
+const FATTR_MTIME_NOW: u32 = 1 << 8;
+
+/// Size of `struct fuse_in_header`.
+const IN_HEADER_SIZE: usize = 40;
+
+/// The largest write the kernel is allowed to send.
+const MAX_WRITE: usize = 32 * PAGE_SIZE;
+
+/// How long, in seconds, the kernel may cache entries and attributes.
+const TTL: u64 = 1;
+
+/// Decodes the arguments of a request.
+struct Args<'a>(&'a [u8]);
+
+impl<'a> Args<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.0.len() < n {
+            return Err(EINVAL);
+        }
+
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_ne_bytes(
+            self.bytes(4)?.try_into().map_err(|_| EINVAL)?,
+        ))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_ne_bytes(
+            self.bytes(8)?.try_into().map_err(|_| EINVAL)?,
+        ))
+    }
+
+    /// A NUL-terminated name.
+    fn name(&mut self) -> Result<&'a [u8]> {
+        let len = self.0.iter().position(|&b| b == 0).ok_or(EINVAL)?;
+        let name = self.bytes(len)?;
+        self.bytes(1)?;
+
+        Ok(name)
+    }
+}
+
+/// Encodes the body of a reply.
+#[derive(Default)]
+struct Reply(Vec<u8>);
+
+impl Reply {
+    fn u16(&mut self, v: u16) -> &mut Self {
+        self.0.extend_from_slice(&v.to_ne_bytes());
+        self
+    }
+
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_ne_bytes());
+        self
+    }
+
+    fn u64(&mut self, v: u64) -> &mut Self {
+        self.0.extend_from_slice(&v.to_ne_bytes());
+        self
+    }
+
+    fn bytes(&mut self, data: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(data);
+        self
+    }
+
+    /// `struct fuse_attr`.
+    fn attr(&mut self, nodeid: u64, stat: &Stat, blksize: u32) -> &mut Self {
+        let secs = |t: Timespec| t.sec() as u64;
+
+        self.u64(nodeid)
+            .u64(stat.size)
+            .u64(stat.blocks * u64::from(blksize / 512))
+            .u64(secs(stat.atime))
+            .u64(secs(stat.mtime))
+            .u64(secs(stat.ctime))
+            .u32(stat.atime.nsec())
+            .u32(stat.mtime.nsec())
+            .u32(stat.ctime.nsec())
+            .u32(stat.mode)
+            .u32(stat.nlink)
+            .u32(stat.uid)
+            .u32(stat.gid)
+            .u32(0)
+            .u32(blksize)
+            .u32(0)
+    }
+}
+
+/// A node the kernel holds, with the number of lookups it has not forgotten yet.
+struct Node<T: FileSystem> {
+    dentry: ARef<DEntry<T>>,
+    lookups: u64,
+}
+
+/// The state of one FUSE connection.
+///
+/// Node ids are inode numbers, except that the root is always [`FUSE_ROOT_ID`]; the inode that
+/// would have had that number takes the root's instead.
+pub struct Session<'a, T: FileSystem> {
+    vfs: &'a Vfs<T>,
+    root_ino: u64,
+    nodes: BTreeMap<u64, Node<T>>,
+    /// Generation of each node id that has been handed out, bumped when it names a new inode.
+    generations: BTreeMap<u64, u64>,
+    files: BTreeMap<u64, File<T>>,
+    next_fh: u64,
+    destroyed: bool,
+}
+
+impl<'a, T: FileSystem> Session<'a, T> {
+    pub fn new(vfs: &'a Vfs<T>) -> Result<Self> {
+        let root = vfs.root().clone();
+        let root_ino = root.inode().ok_or(ENOENT)?.ino() as u64;
+
+        let mut nodes = BTreeMap::new();
+        nodes.insert(
+            FUSE_ROOT_ID,
+            Node {
+                dentry: root,
+                lookups: 1,
+            },
+        );
+
+        Ok(Self {
+            vfs,
+            root_ino,
+            nodes,
+            generations: BTreeMap::new(),
+            files: BTreeMap::new(),
+            next_fh: 1,
+            destroyed: false,
+        })
+    }
+
+    /// Serves requests read from `dev`, an open `/dev/fuse`, until the filesystem is unmounted.
+    pub fn serve(&mut self, dev: &mut std::fs::File) -> Result {
+        let mut buf = vec![0; MAX_WRITE + PAGE_SIZE];
+
+        while !self.destroyed {
+            let len = match dev.read(&mut buf) {
+                Ok(len) => len,
+                Err(e) => match Error::from(e) {
+                    ENOENT | EINTR | EAGAIN => continue,
+                    ENODEV => return Ok(()),
+                    e => return Err(e),
+                },
+            };
+
+            if let Some(reply) = self.handle(&buf[..len]) {
+                // The kernel fails replies to requests that were interrupted with ENOENT.
+                match dev.write(&reply).map_err(Error::from) {
+                    Ok(_) | Err(ENOENT) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles one request, returning the reply to send, if any.
+    ///
+    /// Requests too short to hold a header get no reply, since there is no `unique` to answer;
+    /// a header whose length does not fit the request is answered with `EINVAL`.
+    pub fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let mut args = Args(request.get(..IN_HEADER_SIZE)?);
+        let len = args.u32().ok()? as usize;
+        let opcode = args.u32().ok()?;
+        let unique = args.u64().ok()?;
+        let nodeid = args.u64().ok()?;
+        let (uid, gid) = (args.u32().ok()?, args.u32().ok()?);
+
+        let result = match request.get(IN_HEADER_SIZE..len) {
+            Some(body) => {
+                // The kernel has checked permissions already (`default_permissions`); the ids of
+                // the caller are still needed for the owner of the files it creates.
+                let _cred = cred::override_creds(Arc::new(Credentials {
+                    dac_override: true,
+                    ..Credentials::user(uid, gid)
+                }));
+
+                self.dispatch(opcode, nodeid, Args(body))
+            }
+            None => Err(EINVAL),
+        };
+
+        let (error, body) = match result {
+            Ok(None) => return None,
+            Ok(Some(reply)) => (0, reply.0),
+            Err(e) => (e.to_errno(), Vec::new()),
+        };
+
+        let mut reply = Reply::default();
+        reply
+            .u32((16 + body.len()) as u32)
+            .u32(error as u32)
+            .u64(unique)
+            .bytes(&body);
+
+        Some(reply.0)
+    }
+
+    fn nodeid(&self, ino: usize) -> u64 {
+        match ino as u64 {
+            ino if ino == self.root_ino => FUSE_ROOT_ID,
+            FUSE_ROOT_ID => self.root_ino,
+            ino => ino,
+        }
+    }
+
+    fn dentry(&self, nodeid: u64) -> Result<ARef<DEntry<T>>> {
+        self.nodes
+            .get(&nodeid)
+            .map(|node| node.dentry.clone())
+            .ok_or(ENOENT)
+    }
+
+    fn file(&self, fh: u64) -> Result<&File<T>> {
+        self.files.get(&fh).ok_or(EBADF)
+    }
+
+    fn add_file(&mut self, file: File<T>) -> u64 {
+        let fh = self.next_fh;
+
+        self.next_fh += 1;
+        self.files.insert(fh, file);
+
+        fh
+    }
+
+    fn forget(&mut self, nodeid: u64, nlookup: u64) {
+        if nodeid == FUSE_ROOT_ID {
+            return;
+        }
+
+        if let Some(node) = self.nodes.get_mut(&nodeid) {
+            node.lookups = node.lookups.saturating_sub(nlookup);
+
+            if node.lookups == 0 {
+                self.nodes.remove(&nodeid);
+            }
+        }
+    }
+
+    /// `struct fuse_attr_out` for `dentry`.
+    fn attr_out(&self, nodeid: u64, dentry: &DEntry<T>) -> Result<Reply> {
+        let inode = dentry.inode().ok_or(ENOENT)?;
+        let stat = self.vfs.getattr(&inode)?;
+        let mut reply = Reply::default();
+
+        reply
+            .u64(TTL)
+            .u32(0)
+            .u32(0)
+            .attr(nodeid, &stat, self.vfs.super_block().blocksize());
+
+        Ok(reply)
+    }
+
+    /// `struct fuse_entry_out` for `dentry`, which the kernel now holds one more lookup of.
+    fn entry_out(&mut self, dentry: ARef<DEntry<T>>) -> Result<Reply> {
+        let inode = dentry.inode().ok_or(ENOENT)?;
+        let stat = self.vfs.getattr(&inode)?;
+        let nodeid = self.nodeid(inode.ino());
+
+        let reused = self
+            .nodes
+            .get(&nodeid)
+            .and_then(|node| node.dentry.inode())
+            .is_some_and(|held| !ARef::ptr_eq(&held, &inode));
+        let generation = self.generations.entry(nodeid).or_default();
+        if reused {
+            *generation += 1;
+        }
+        let generation = *generation;
+
+        let node = self.nodes.entry(nodeid).or_insert(Node {
+            dentry: dentry.clone(),
+            lookups: 0,
+        });
+        node.dentry = dentry;
+        node.lookups += 1;
+
+        let mut reply = Reply::default();
+        reply
+            .u64(nodeid)
+            .u64(generation)
+            .u64(TTL)
+            .u64(TTL)
+            .u32(0)
+            .u32(0)
+            .attr(nodeid, &stat, self.vfs.super_block().blocksize());
+
+        Ok(reply)
+    }
+
+    fn dispatch(&mut self, opcode: u32, nodeid: u64, mut args: Args<'_>) -> Result<Option<Reply>> {
+        let vfs = self.vfs;
+        let mut reply = Reply::default();
+
+        match opcode {
+            FUSE_INIT => {
+                let major = args.u32()?;
+                let minor = args.u32()?;
+                let max_readahead = args.u32()?;
+
+                // A newer kernel resends INIT with our major version.
+                reply.u32(FUSE_KERNEL_VERSION);
+                if major == FUSE_KERNEL_VERSION {
+                    if minor < 23 {
+                        return Err(EINVAL);
+                    }
+
+                    reply
+                        .u32(minor.min(FUSE_KERNEL_MINOR_VERSION))
+                        .u32(max_readahead)
+                        .u32(FUSE_BIG_WRITES)
+                        .u16(16)
+                        .u16(12)
+                        .u32(MAX_WRITE as u32)
+                        .u32(vfs.super_block().time_gran())
+                        .u16(0)
+                        .u16(0)
+                        .u32(0)
+                        .bytes(&[0; 28]);
+                }
+            }
+            FUSE_DESTROY => self.destroyed = true,
+            FUSE_FORGET => {
+                self.forget(nodeid, args.u64()?);
+                return Ok(None);
+            }
+            FUSE_BATCH_FORGET => {
+                let count = args.u32()?;
+                args.u32()?;
+
+                for _ in 0..count {
+                    let (nodeid, nlookup) = (args.u64()?, args.u64()?);
+                    self.forget(nodeid, nlookup);
+                }
+
+                return Ok(None);
+            }
+            FUSE_INTERRUPT => return Ok(None),
+            FUSE_LOOKUP => {
+                let dentry = vfs.lookup_at(&self.dentry(nodeid)?, args.name()?)?;
+                reply = self.entry_out(dentry)?;
+            }
+            FUSE_GETATTR => {
+                let dentry = self.dentry(nodeid)?;
+                reply = self.attr_out(nodeid, &dentry)?;
+            }
+            FUSE_SETATTR => {
+                let valid = args.u32()?;
+                args.bytes(4 + 8)?;
+                let size = args.u64()?;
+                args.u64()?;
+                let (atime, mtime) = (args.u64()?, args.u64()?);
+                args.u64()?;
+                let (atimensec, mtimensec) = (args.u32()?, args.u32()?);
+                args.u32()?;
+                let mode = args.u32()?;
+                args.u32()?;
+                let (uid, gid) = (args.u32()?, args.u32()?);
+
+                let dentry = self.dentry(nodeid)?;
+                let inode = dentry.inode().ok_or(ENOENT)?;
+                let time = |set, now, sec, nsec| -> Result<Option<Timespec>> {
+                    Ok(match () {
+                        _ if valid & now != 0 => Some(inode.current_time()),
+                        _ if valid & set != 0 => Some(Timespec::new(sec, nsec)?),
+                        _ => None,
+                    })
+                };
+
+                let attr = Attr {
+                    mode: (valid & FATTR_MODE != 0).then_some(mode),
+                    uid: (valid & FATTR_UID != 0).then_some(uid),
+                    gid: (valid & FATTR_GID != 0).then_some(gid),
+                    size: (valid & FATTR_SIZE != 0).then_some(size),
+                    atime: time(FATTR_ATIME, FATTR_ATIME_NOW, atime, atimensec)?,
+                    mtime: time(FATTR_MTIME, FATTR_MTIME_NOW, mtime, mtimensec)?,
+                };
+
+                vfs.setattr(&inode, &attr)?;
+                reply = self.attr_out(nodeid, &dentry)?;
+            }
+            FUSE_MKDIR => {
+                let mode = args.u32()?;
+                let umask = args.u32()?;
+                let dentry = vfs.mkdir_at(&self.dentry(nodeid)?, args.name()?, mode & !umask)?;
+
+                reply = self.entry_out(dentry)?;
+            }
+            FUSE_CREATE => {
+                let flags = args.u32()?;
+                let mode = args.u32()?;
+                let umask = args.u32()?;
+                args.u32()?;
+
+                let dentry = vfs.create_at(&self.dentry(nodeid)?, args.name()?, mode & !umask)?;
+                let file = vfs.open_dentry(dentry.clone(), flags)?;
+
+                reply = self.entry_out(dentry)?;
+                let fh = self.add_file(file);
+                reply.u64(fh).u32(0).u32(0);
+            }
+            FUSE_UNLINK => vfs.unlink_at(&self.dentry(nodeid)?, args.name()?)?,
+            FUSE_RMDIR => vfs.rmdir_at(&self.dentry(nodeid)?, args.name()?)?,
+            FUSE_RENAME | FUSE_RENAME2 => {
+                let newdir = args.u64()?;
+
+                if opcode == FUSE_RENAME2 {
+                    let flags = args.u32()?;
+                    args.u32()?;
+
+                    if flags != 0 {
+                        return Err(EINVAL);
+                    }
+                }
+
+                let (old_name, new_name) = (args.name()?, args.name()?);
+                vfs.rename_at(
+                    &self.dentry(nodeid)?,
+                    old_name,
+                    &self.dentry(newdir)?,
+                    new_name,
+                )?;
+            }
+            FUSE_OPEN | FUSE_OPENDIR => {
+                let flags = match opcode {
+                    FUSE_OPEN => args.u32()?,
+                    _ => O_RDONLY | O_DIRECTORY,
+                };
+
+                let file = vfs.open_dentry(self.dentry(nodeid)?, flags)?;
+                let fh = self.add_file(file);
+                reply.u64(fh).u32(0).u32(0);
+            }
+            FUSE_RELEASE | FUSE_RELEASEDIR => {
+                self.files.remove(&args.u64()?);
+            }
+            FUSE_READ => {
+                let fh = args.u64()?;
+                let offset = Offset::try_from(args.u64()?)?;
+                let size = args.u32()? as usize;
+
+                let mut buf = vec![0; size];
+                let read = vfs.read_at(self.file(fh)?, &mut buf, offset)?;
+                reply.bytes(&buf[..read]);
+            }
+            FUSE_WRITE => {
+                let fh = args.u64()?;
+                let offset = Offset::try_from(args.u64()?)?;
+                let size = args.u32()? as usize;
+                args.bytes(4 + 8 + 4 + 4)?;
+
+                let written = vfs.write_at(self.file(fh)?, args.bytes(size)?, offset)?;
+                reply.u32(written as u32).u32(0);
+            }
+            FUSE_READDIR => {
+                let fh = args.u64()?;
+                let offset = Offset::try_from(args.u64()?)?;
+                let size = args.u32()? as usize;
+
+                for entry in vfs.read_dir(self.file(fh)?, offset, size)? {
+                    let reclen = (24 + entry.name.len()).next_multiple_of(8);
+                    if reply.0.len() + reclen > size {
+                        break;
+                    }
+
+                    reply
+                        .u64(self.nodeid(entry.ino as usize))
+                        .u64(entry.pos as u64)
+                        .u32(entry.name.len() as u32)
+                        .u32(u8::from(entry.etype).into())
+                        .bytes(&entry.name)
+                        .bytes(&[0; 7][..reclen - 24 - entry.name.len()]);
+                }
+            }
+            FUSE_STATFS => {
+                let st = fs::statfs(vfs.super_block())?;
+
+                reply
+                    .u64(st.blocks)
+                    .u64(st.bfree)
+                    .u64(st.bavail)
+                    .u64(st.files)
+                    .u64(st.ffree)
+                    .u32(st.bsize as u32)
+                    .u32(st.namelen as u32)
+                    .u32(st.bsize as u32)
+                    .u32(0)
+                    .bytes(&[0; 24]);
+            }
+            FUSE_FSYNC | FUSE_FSYNCDIR => writeback::sync_filesystem(vfs.super_block())?,
+            // Permissions are checked by the kernel, which is mounted with `default_permissions`.
+            FUSE_FLUSH | FUSE_ACCESS => {}
+            _ => return Err(ENOSYS),
+        }
+
+        Ok(Some(reply))
+    }
+}
+
+unsafe extern "C" {
+    fn mount(
+        source: *const c_char,
+        target: *const c_char,
+        fstype: *const c_char,
+        flags: c_ulong,
+        data: *const c_void,
+    ) -> c_int;
+    fn umount2(target: *const c_char, flags: c_int) -> c_int;
+    fn getuid() -> u32;
+    fn getgid() -> u32;
+}
+
+const MS_NOSUID: c_ulong = 2;
+const MS_NODEV: c_ulong = 4;
+const MNT_DETACH: c_int = 2;
+
+fn c_string(bytes: &[u8]) -> Result<CString> {
+    CString::new(bytes).map_err(|_| EINVAL)
+}
+
+/// Opens `/dev/fuse` and mounts it on `mountpoint` as `fuse.<name>`, which is what `fusermount`
+/// does for libfuse. Needs `CAP_SYS_ADMIN`.
+pub fn mount_fuse<T: FileSystem>(source: &str, mountpoint: &Path) -> Result<std::fs::File> {
+    let dev = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/fuse")?;
+
+    // SAFETY: `getuid` and `getgid` cannot fail and have no preconditions.
+    let (uid, gid) = unsafe { (getuid(), getgid()) };
+    let data = format!(
+        "fd={},rootmode={:o},user_id={uid},group_id={gid},default_permissions",
+        dev.as_raw_fd(),
+        S_IFDIR,
+    );
+
+    let source = c_string(source.as_bytes())?;
+    let target = c_string(mountpoint.as_os_str().as_bytes())?;
+    let fstype = c_string(format!("fuse.{}", T::NAME).as_bytes())?;
+    let data = c_string(data.as_bytes())?;
+
+    // SAFETY: All pointers are to NUL-terminated strings that outlive the call.
+    let ret = unsafe {
+        mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            fstype.as_ptr(),
+            MS_NOSUID | MS_NODEV,
+            data.as_ptr().cast(),
+        )
+    };
+
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    Ok(dev)
+}
+
+/// Lazily unmounts the FUSE filesystem at `mountpoint`.
+pub fn unmount_fuse(mountpoint: &Path) -> Result {
+    let target = c_string(mountpoint.as_os_str().as_bytes())?;
+
+    // SAFETY: `target` is a NUL-terminated string that outlives the call.
+    if unsafe { umount2(target.as_ptr(), MNT_DETACH) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestFs;
+    use crate::types::code::EEXIST;
+
+    fn request(opcode: u32, unique: u64, nodeid: u64, body: &[u8]) -> Vec<u8> {
+        let mut req = Reply::default();
+
+        req.u32((IN_HEADER_SIZE + body.len()) as u32)
+            .u32(opcode)
+            .u64(unique)
+            .u64(nodeid)
+            .bytes(&[0; 16])
+            .bytes(body);
+
+        req.0
+    }
+
+    /// The error and body of a reply to request `unique`.
+    fn parse(reply: Option<Vec<u8>>, unique: u64) -> (i32, Vec<u8>) {
+        let reply = reply.unwrap();
+        let mut args = Args(&reply);
+
+        assert_eq!(args.u32().unwrap() as usize, reply.len());
+        let error = args.u32().unwrap() as i32;
+        assert_eq!(args.u64().unwrap(), unique);
+
+        (error, args.0.to_vec())
+    }
+
+    #[test]
+    fn requests_map_onto_the_vfs() {
+        let vfs = Vfs::<TestFs>::mount(None, "").unwrap();
+        let mut session = Session::new(&vfs).unwrap();
+
+        let init = [7u32, 31, 4096, 0].map(u32::to_ne_bytes).concat();
+        let (error, body) = parse(session.handle(&request(FUSE_INIT, 1, 0, &init)), 1);
+        assert_eq!((error, body.len()), (0, 64));
+        assert_eq!(body[..8], [7u32, 31].map(u32::to_ne_bytes).concat());
+
+        let (error, body) = parse(session.handle(&request(FUSE_LOOKUP, 2, 1, b"dir\0")), 2);
+        assert_eq!((error, body.len()), (0, 128));
+        let nodeid = Args(&body).u64().unwrap();
+        assert_eq!(session.nodes[&nodeid].lookups, 1);
+
+        let (error, body) = parse(
+            session.handle(&request(FUSE_GETATTR, 3, nodeid, &[0; 16])),
+            3,
+        );
+        let mut attr = Args(&body[16..]);
+        assert_eq!((error, attr.u64().unwrap()), (0, nodeid));
+        attr.bytes(5 * 8 + 3 * 4).unwrap();
+        assert_eq!(attr.u32().unwrap(), S_IFDIR | 0o755);
+
+        let (error, _) = parse(session.handle(&request(FUSE_LOOKUP, 4, 1, b"missing\0")), 4);
+        assert_eq!(error, ENOENT.to_errno());
+
+        let (error, _) = parse(session.handle(&request(FUSE_MKDIR, 5, 1, b"\0")), 5);
+        assert_eq!(error, EINVAL.to_errno());
+
+        let mkdir = [&[0o755u32, 0].map(u32::to_ne_bytes).concat()[..], b"dir\0"].concat();
+        let (error, _) = parse(session.handle(&request(FUSE_MKDIR, 6, 1, &mkdir)), 6);
+        assert_eq!(error, EEXIST.to_errno());
+
+        let (error, _) = parse(session.handle(&request(0xffff, 7, 1, &[])), 7);
+        assert_eq!(error, ENOSYS.to_errno());
+
+        let forget = request(FUSE_FORGET, 8, nodeid, &1u64.to_ne_bytes());
+        assert!(session.handle(&forget).is_none());
+        assert!(!session.nodes.contains_key(&nodeid));
+        assert!(session.handle(&[0; 8]).is_none());
+
+        let mut short = request(FUSE_GETATTR, 9, 1, &[0; 16]);
+        short[..4].copy_from_slice(&8u32.to_ne_bytes());
+        let (error, _) = parse(session.handle(&short), 9);
+        assert_eq!(error, EINVAL.to_errno());
+        short[..4].copy_from_slice(&1000u32.to_ne_bytes());
+        let (error, _) = parse(session.handle(&short), 9);
+        assert_eq!(error, EINVAL.to_errno());
+
+        drop(session);
+        vfs.umount().unwrap();
+    }
+
+    #[test]
+    fn reused_node_ids_get_a_new_generation() {
+        let vfs = Vfs::<TestFs>::mount(None, "").unwrap();
+        let mut session = Session::new(&vfs).unwrap();
+        let lookup = |session: &mut Session<'_, TestFs>, unique| {
+            let (error, body) = parse(
+                session.handle(&request(FUSE_LOOKUP, unique, 1, b"dir\0")),
+                unique,
+            );
+            let mut entry = Args(&body);
+            assert_eq!(error, 0);
+            (entry.u64().unwrap(), entry.u64().unwrap())
+        };
+
+        let (nodeid, generation) = lookup(&mut session, 1);
+        assert_eq!(generation, 0);
+        assert_eq!(lookup(&mut session, 2), (nodeid, 0));
+
+        // The kernel still holds the node, but its number now belongs to another inode.
+        session.nodes.get_mut(&nodeid).unwrap().dentry = vfs.root().clone();
+        assert_eq!(lookup(&mut session, 3), (nodeid, 1));
+        assert_eq!(lookup(&mut session, 4), (nodeid, 1));
+        assert_eq!(session.nodes[&nodeid].lookups, 4);
+
+        drop(session);
+        vfs.umount().unwrap();
+    }
+}
@@ -1,58 +1,952 @@
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::PAGE_SIZE;
+use crate::address_space::{self, AddressSpace};
+use crate::block::BlockDevice;
+use crate::cred;
+use crate::dentry::{self, DEntry};
+use crate::file;
 use crate::fs::{FileSystem, Offset};
-use crate::types::{Error, Result};
+use crate::sb::{Ready, SuperBlock};
+use crate::time::{self, Timespec};
+use crate::types::code::{EACCES, EFBIG, EIO, ENOTSUPP, EPERM, ERANGE};
+use crate::types::{ARef, Lockable, Locked, Result, RwSemaphore};
+use crate::uapi::{self, gid_t, mode_t, uid_t};
+use crate::writeback;
+
+pub use crate::types::{ReadSem, WriteSem};
 
 pub enum INodeState<T: FileSystem + ?Sized> {
-    Existing(INode<T>),
+    Existing(ARef<INode<T>>),
     Uninitilized(New<T>),
 }
 
+/// Attributes of an inode that change over its lifetime.
+#[derive(Clone, Copy)]
+struct Attrs {
+    mode: mode_t,
+    size: u64,
+    blocks: u64,
+    nlink: u32,
+    uid: uid_t,
+    gid: gid_t,
+    atime: Timespec,
+    mtime: Timespec,
+    ctime: Timespec,
+}
+
 pub struct INode<T: FileSystem + ?Sized> {
-    pub ino: usize,
-    pub data: Option<T::INodeData>,
+    ino: usize,
+    sb: SuperBlock<T, Ready>,
+    ops: Ops<T>,
+    fops: file::Ops<T>,
+    mapping: AddressSpace<T>,
+    attrs: Mutex<Attrs>,
+    /// Whether the inode is on the dirty list of its superblock.
+    dirty: AtomicBool,
+    /// The equivalent of `i_rwsem`, taken through [`INode::lock`] and [`INode::lock_shared`].
+    rwsem: RwSemaphore,
+    data: T::INodeData,
+}
+
+impl<T: FileSystem + ?Sized> INode<T> {
+    pub fn ino(&self) -> usize {
+        self.ino
+    }
+
+    pub fn super_block(&self) -> &SuperBlock<T, Ready> {
+        &self.sb
+    }
+
+    pub fn data(&self) -> &T::INodeData {
+        &self.data
+    }
+
+    pub fn ops(&self) -> Ops<T> {
+        self.ops
+    }
+
+    pub fn fops(&self) -> file::Ops<T> {
+        self.fops
+    }
+
+    /// The page cache of the inode, the equivalent of `i_mapping`.
+    pub fn mapping(&self) -> &AddressSpace<T> {
+        &self.mapping
+    }
+
+    /// Takes the inode lock exclusively, the equivalent of `inode_lock`.
+    pub fn lock(&self) -> Locked<&Self, WriteSem> {
+        Locked::acquire(self)
+    }
+
+    /// Takes the inode lock shared, the equivalent of `inode_lock_shared`.
+    pub fn lock_shared(&self) -> Locked<&Self, ReadSem> {
+        Locked::acquire(self)
+    }
+
+    /// The current time at the granularity of the filesystem, the equivalent of `current_time`.
+    pub fn current_time(&self) -> Timespec {
+        self.sb.timestamp_truncate(time::current_time())
+    }
+
+    /// Queues the inode to be written back, the equivalent of `mark_inode_dirty`.
+    pub fn mark_dirty(&self) {
+        if !self.dirty.swap(true, Ordering::AcqRel) {
+            self.sb.queue_dirty_inode(self.ino);
+        }
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Acquire)
+    }
+
+    /// Clears the dirty flag, returning whether it was set.
+    pub(crate) fn clear_dirty(&self) -> bool {
+        self.dirty.swap(false, Ordering::AcqRel)
+    }
+
+    fn attrs(&self) -> Attrs {
+        *self.attrs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Attrs)) {
+        f(&mut self.attrs.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    pub fn mode(&self) -> mode_t {
+        self.attrs().mode
+    }
+
+    pub fn size(&self) -> u64 {
+        self.attrs().size
+    }
+
+    pub fn blocks(&self) -> u64 {
+        self.attrs().blocks
+    }
+
+    pub fn nlink(&self) -> u32 {
+        self.attrs().nlink
+    }
+
+    pub fn uid(&self) -> uid_t {
+        self.attrs().uid
+    }
+
+    pub fn gid(&self) -> gid_t {
+        self.attrs().gid
+    }
+
+    pub fn atime(&self) -> Timespec {
+        self.attrs().atime
+    }
+
+    pub fn mtime(&self) -> Timespec {
+        self.attrs().mtime
+    }
+
+    pub fn ctime(&self) -> Timespec {
+        self.attrs().ctime
+    }
+
+    pub fn set_mode(&self, mode: mode_t) {
+        self.update(|a| a.mode = mode)
+    }
+
+    pub fn set_size(&self, size: u64) {
+        self.update(|a| a.size = size)
+    }
+
+    pub fn set_blocks(&self, blocks: u64) {
+        self.update(|a| a.blocks = blocks)
+    }
+
+    pub fn set_nlink(&self, nlink: u32) {
+        self.update(|a| a.nlink = nlink)
+    }
+
+    pub fn set_owner(&self, uid: uid_t, gid: gid_t) {
+        self.update(|a| {
+            a.uid = uid;
+            a.gid = gid;
+        })
+    }
+
+    pub fn set_atime(&self, atime: Timespec) {
+        self.update(|a| a.atime = atime)
+    }
+
+    pub fn set_mtime(&self, mtime: Timespec) {
+        self.update(|a| a.mtime = mtime)
+    }
+
+    pub fn set_ctime(&self, ctime: Timespec) {
+        self.update(|a| a.ctime = ctime)
+    }
 }
 
-impl<T: FileSystem> INode<T> {
-    pub(crate) fn new(ino: usize, data: Option<T::INodeData>) -> Self {
-        Self { ino, data }
+impl<T: FileSystem + ?Sized> Lockable for INode<T> {
+    fn rwsem(&self) -> &RwSemaphore {
+        &self.rwsem
     }
 }
 
+impl<T: FileSystem + ?Sized> Drop for INode<T> {
+    fn drop(&mut self) {
+        let ops = self.sb.ops();
+
+        // Errors cannot be reported from here; callers that care write back first.
+        if self.nlink() > 0 {
+            let _ = writeback::write_inode_now(self, true);
+        }
+
+        ops.evict_inode(self);
+        ops.destroy_inode(self);
+        self.sb.remove_inode(self.ino);
+    }
+}
+
+/// Initial attributes and filesystem data of a new inode.
+pub struct Params<T> {
+    pub mode: mode_t,
+    pub size: u64,
+    pub blocks: u64,
+    pub nlink: u32,
+    pub uid: uid_t,
+    pub gid: gid_t,
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
+    pub value: T,
+}
+
+/// An inode that is not in the cache yet; [`New::init`] completes it.
 pub struct New<T: FileSystem + ?Sized> {
-    pub ino: usize,
-    pub data: Option<T::INodeData>,
+    sb: SuperBlock<T, Ready>,
+    ino: usize,
+    ops: Ops<T>,
+    fops: file::Ops<T>,
+    aops: address_space::Ops<T>,
 }
 
 impl<T: FileSystem + ?Sized> New<T> {
-    pub(crate) fn new(ino: usize, data: Option<T::INodeData>) -> Self {
-        Self { ino, data }
+    #[allow(clippy::self_named_constructors)]
+    pub(crate) fn new(sb: SuperBlock<T, Ready>, ino: usize) -> Self {
+        Self {
+            sb,
+            ino,
+            ops: Ops::empty(),
+            fops: file::Ops::empty(),
+            aops: address_space::Ops::empty(),
+        }
+    }
+
+    pub fn ino(&self) -> usize {
+        self.ino
+    }
+
+    pub fn set_iops(&mut self, ops: Ops<T>) -> &mut Self {
+        self.ops = ops;
+        self
+    }
+
+    pub fn set_fops(&mut self, fops: file::Ops<T>) -> &mut Self {
+        self.fops = fops;
+        self
+    }
+
+    pub fn set_aops(&mut self, aops: address_space::Ops<T>) -> &mut Self {
+        self.aops = aops;
+        self
     }
+
+    pub fn init(self, params: Params<T::INodeData>) -> Result<ARef<INode<T>>>
+    where
+        T: Sized,
+    {
+        let sb = self.sb.handle();
+
+        sb.insert_inode(INode {
+            ino: self.ino,
+            sb: self.sb,
+            ops: self.ops,
+            fops: self.fops,
+            mapping: AddressSpace::new(self.aops),
+            attrs: Mutex::new(Attrs {
+                mode: params.mode,
+                size: params.size,
+                blocks: params.blocks,
+                nlink: params.nlink,
+                uid: params.uid,
+                gid: params.gid,
+                atime: params.atime,
+                mtime: params.mtime,
+                ctime: params.ctime,
+            }),
+            dirty: AtomicBool::new(false),
+            rwsem: RwSemaphore::new(),
+            data: params.value,
+        })
+    }
+}
+
+/// Attributes reported by [`Operations::getattr`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stat {
+    pub ino: usize,
+    pub mode: mode_t,
+    pub nlink: u32,
+    pub uid: uid_t,
+    pub gid: gid_t,
+    pub size: u64,
+    pub blocks: u64,
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
 }
 
+/// Attribute changes requested through [`Operations::setattr`]; `None` leaves a field alone.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Attr {
+    pub mode: Option<mode_t>,
+    pub uid: Option<uid_t>,
+    pub gid: Option<gid_t>,
+    pub size: Option<u64>,
+    pub atime: Option<Timespec>,
+    pub mtime: Option<Timespec>,
+}
+
+/// Fills a [`Stat`] from the attributes cached in `inode`.
+pub fn generic_fillattr<T: FileSystem + ?Sized>(inode: &INode<T>) -> Stat {
+    let attrs = inode.attrs();
+
+    Stat {
+        ino: inode.ino,
+        mode: attrs.mode,
+        nlink: attrs.nlink,
+        uid: attrs.uid,
+        gid: attrs.gid,
+        size: attrs.size,
+        blocks: attrs.blocks,
+        atime: attrs.atime,
+        mtime: attrs.mtime,
+        ctime: attrs.ctime,
+    }
+}
+
+pub const MAY_EXEC: u32 = 0o1;
+pub const MAY_WRITE: u32 = 0o2;
+pub const MAY_READ: u32 = 0o4;
+
+/// Checks the `MAY_*` access in `mask` to `inode` against its mode bits for the current
+/// credentials, failing with `EACCES`.
+///
+/// Credentials with `dac_override` may read and write anything, search any directory and execute
+/// any file that has an execute bit set.
+pub fn generic_permission<T: FileSystem + ?Sized>(inode: &INode<T>, mask: u32) -> Result {
+    let cred = cred::current();
+    let mode = inode.mode();
+
+    let perm = if cred.fsuid == inode.uid() {
+        mode >> 6
+    } else if cred.in_group(inode.gid()) {
+        mode >> 3
+    } else {
+        mode
+    };
+
+    if mask & !perm & 0o7 == 0 {
+        return Ok(());
+    }
+
+    if cred.dac_override && (mask & MAY_EXEC == 0 || uapi::s_isdir(mode) || mode & 0o111 != 0) {
+        return Ok(());
+    }
+
+    Err(EACCES)
+}
+
+/// Checks that the current credentials may make the changes in `attr` to `inode`.
+///
+/// Changing the size needs write permission, failing with `EACCES`. Changing the mode or times
+/// needs the caller to own the inode, changing the owner needs `dac_override`, and changing the
+/// group needs `dac_override` or an owner who is in the new group; all fail with `EPERM`.
+/// Setting an owner or group to what it already is is always allowed.
+pub fn setattr_prepare<T: FileSystem + ?Sized>(inode: &INode<T>, attr: &Attr) -> Result {
+    let cred = cred::current();
+
+    if attr.size.is_some() {
+        generic_permission(inode, MAY_WRITE)?;
+    }
+
+    let owner = cred.fsuid == inode.uid() || cred.dac_override;
+    if (attr.mode.is_some() || attr.atime.is_some() || attr.mtime.is_some()) && !owner {
+        return Err(EPERM);
+    }
+
+    if attr.uid.is_some_and(|uid| uid != inode.uid()) && !cred.dac_override {
+        return Err(EPERM);
+    }
+
+    let chgrp_ok = |gid| cred.fsuid == inode.uid() && cred.in_group(gid);
+    if attr
+        .gid
+        .is_some_and(|gid| gid != inode.gid() && !chgrp_ok(gid) && !cred.dac_override)
+    {
+        return Err(EPERM);
+    }
+
+    Ok(())
+}
+
+type LockedINode<'a, T, L> = Locked<&'a INode<T>, L>;
+
+/// Inode operations, the equivalent of `struct inode_operations`.
+///
+/// Operations that change the namespace fail with `EPERM` unless implemented, the others with
+/// `ENOTSUPP`.
+pub trait Operations {
+    type FileSystem: FileSystem + ?Sized;
+
+    /// Looks up `dentry` in `parent` and instantiates it with [`dentry::Unhashed::splice_alias`].
+    fn lookup(
+        _parent: &LockedINode<'_, Self::FileSystem, ReadSem>,
+        _dentry: dentry::Unhashed<'_, Self::FileSystem>,
+    ) -> Result<Option<ARef<DEntry<Self::FileSystem>>>> {
+        Err(ENOTSUPP)
+    }
+
+    fn create(
+        _dir: &LockedINode<'_, Self::FileSystem, WriteSem>,
+        _name: &[u8],
+        _mode: mode_t,
+    ) -> Result<ARef<INode<Self::FileSystem>>> {
+        Err(EPERM)
+    }
+
+    fn mkdir(
+        _dir: &LockedINode<'_, Self::FileSystem, WriteSem>,
+        _name: &[u8],
+        _mode: mode_t,
+    ) -> Result<ARef<INode<Self::FileSystem>>> {
+        Err(EPERM)
+    }
+
+    fn unlink(_dir: &LockedINode<'_, Self::FileSystem, WriteSem>, _name: &[u8]) -> Result {
+        Err(EPERM)
+    }
+
+    fn rmdir(_dir: &LockedINode<'_, Self::FileSystem, WriteSem>, _name: &[u8]) -> Result {
+        Err(EPERM)
+    }
+
+    fn rename(
+        _old_dir: &LockedINode<'_, Self::FileSystem, WriteSem>,
+        _old_name: &[u8],
+        _new_dir: &LockedINode<'_, Self::FileSystem, WriteSem>,
+        _new_name: &[u8],
+        _flags: u32,
+    ) -> Result {
+        Err(EPERM)
+    }
+
+    fn link(
+        _old: &INode<Self::FileSystem>,
+        _dir: &LockedINode<'_, Self::FileSystem, WriteSem>,
+        _name: &[u8],
+    ) -> Result {
+        Err(EPERM)
+    }
+
+    fn symlink(
+        _dir: &LockedINode<'_, Self::FileSystem, WriteSem>,
+        _name: &[u8],
+        _target: &[u8],
+    ) -> Result<ARef<INode<Self::FileSystem>>> {
+        Err(EPERM)
+    }
+
+    fn mknod(
+        _dir: &LockedINode<'_, Self::FileSystem, WriteSem>,
+        _name: &[u8],
+        _mode: mode_t,
+        _dev: u32,
+    ) -> Result<ARef<INode<Self::FileSystem>>> {
+        Err(EPERM)
+    }
+
+    fn setattr(_inode: &LockedINode<'_, Self::FileSystem, WriteSem>, _attr: &Attr) -> Result {
+        Err(ENOTSUPP)
+    }
+
+    fn getattr(_inode: &INode<Self::FileSystem>) -> Result<Stat> {
+        Err(ENOTSUPP)
+    }
+}
+
+/// A table of inode operations, created from an [`Operations`] implementation.
+pub struct Ops<T: FileSystem + ?Sized>(&'static Table<T>);
+
+impl<T: FileSystem + ?Sized> Clone for Ops<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: FileSystem + ?Sized> Copy for Ops<T> {}
+
+#[allow(clippy::type_complexity)]
+struct Table<T: FileSystem + ?Sized> {
+    lookup: fn(
+        &LockedINode<'_, T, ReadSem>,
+        dentry::Unhashed<'_, T>,
+    ) -> Result<Option<ARef<DEntry<T>>>>,
+    create: fn(&LockedINode<'_, T, WriteSem>, &[u8], mode_t) -> Result<ARef<INode<T>>>,
+    mkdir: fn(&LockedINode<'_, T, WriteSem>, &[u8], mode_t) -> Result<ARef<INode<T>>>,
+    unlink: fn(&LockedINode<'_, T, WriteSem>, &[u8]) -> Result,
+    rmdir: fn(&LockedINode<'_, T, WriteSem>, &[u8]) -> Result,
+    rename: fn(
+        &LockedINode<'_, T, WriteSem>,
+        &[u8],
+        &LockedINode<'_, T, WriteSem>,
+        &[u8],
+        u32,
+    ) -> Result,
+    link: fn(&INode<T>, &LockedINode<'_, T, WriteSem>, &[u8]) -> Result,
+    symlink: fn(&LockedINode<'_, T, WriteSem>, &[u8], &[u8]) -> Result<ARef<INode<T>>>,
+    mknod: fn(&LockedINode<'_, T, WriteSem>, &[u8], mode_t, u32) -> Result<ARef<INode<T>>>,
+    setattr: fn(&LockedINode<'_, T, WriteSem>, &Attr) -> Result,
+    getattr: fn(&INode<T>) -> Result<Stat>,
+}
+
+struct TableFor<U: ?Sized>(PhantomData<U>);
+
+impl<U: Operations + ?Sized> TableFor<U> {
+    const TABLE: Table<U::FileSystem> = Table {
+        lookup: U::lookup,
+        create: U::create,
+        mkdir: U::mkdir,
+        unlink: U::unlink,
+        rmdir: U::rmdir,
+        rename: U::rename,
+        link: U::link,
+        symlink: U::symlink,
+        mknod: U::mknod,
+        setattr: U::setattr,
+        getattr: U::getattr,
+    };
+}
+
+/// Operations for inodes that were not given any.
+struct NoOps<T: ?Sized>(PhantomData<T>);
+
+impl<T: FileSystem + ?Sized> Operations for NoOps<T> {
+    type FileSystem = T;
+}
+
+impl<T: FileSystem + ?Sized> Ops<T> {
+    pub const fn new<U: Operations<FileSystem = T> + ?Sized>() -> Self {
+        Self(&TableFor::<U>::TABLE)
+    }
+
+    pub const fn empty() -> Self {
+        Self::new::<NoOps<T>>()
+    }
+
+    pub fn lookup(
+        &self,
+        parent: &LockedINode<'_, T, ReadSem>,
+        dentry: dentry::Unhashed<'_, T>,
+    ) -> Result<Option<ARef<DEntry<T>>>> {
+        (self.0.lookup)(parent, dentry)
+    }
+
+    pub fn create(
+        &self,
+        dir: &LockedINode<'_, T, WriteSem>,
+        name: &[u8],
+        mode: mode_t,
+    ) -> Result<ARef<INode<T>>> {
+        (self.0.create)(dir, name, mode)
+    }
+
+    pub fn mkdir(
+        &self,
+        dir: &LockedINode<'_, T, WriteSem>,
+        name: &[u8],
+        mode: mode_t,
+    ) -> Result<ARef<INode<T>>> {
+        (self.0.mkdir)(dir, name, mode)
+    }
+
+    pub fn unlink(&self, dir: &LockedINode<'_, T, WriteSem>, name: &[u8]) -> Result {
+        (self.0.unlink)(dir, name)
+    }
+
+    pub fn rmdir(&self, dir: &LockedINode<'_, T, WriteSem>, name: &[u8]) -> Result {
+        (self.0.rmdir)(dir, name)
+    }
+
+    pub fn rename(
+        &self,
+        old_dir: &LockedINode<'_, T, WriteSem>,
+        old_name: &[u8],
+        new_dir: &LockedINode<'_, T, WriteSem>,
+        new_name: &[u8],
+        flags: u32,
+    ) -> Result {
+        (self.0.rename)(old_dir, old_name, new_dir, new_name, flags)
+    }
+
+    pub fn link(&self, old: &INode<T>, dir: &LockedINode<'_, T, WriteSem>, name: &[u8]) -> Result {
+        (self.0.link)(old, dir, name)
+    }
+
+    pub fn symlink(
+        &self,
+        dir: &LockedINode<'_, T, WriteSem>,
+        name: &[u8],
+        target: &[u8],
+    ) -> Result<ARef<INode<T>>> {
+        (self.0.symlink)(dir, name, target)
+    }
+
+    pub fn mknod(
+        &self,
+        dir: &LockedINode<'_, T, WriteSem>,
+        name: &[u8],
+        mode: mode_t,
+        dev: u32,
+    ) -> Result<ARef<INode<T>>> {
+        (self.0.mknod)(dir, name, mode, dev)
+    }
+
+    /// Checks the change with [`setattr_prepare`] before handing it to the filesystem.
+    pub fn setattr(&self, inode: &LockedINode<'_, T, WriteSem>, attr: &Attr) -> Result {
+        setattr_prepare(inode, attr)?;
+        (self.0.setattr)(inode, attr)
+    }
+
+    pub fn getattr(&self, inode: &INode<T>) -> Result<Stat> {
+        (self.0.getattr)(inode)
+    }
+}
+
+/// Maps the byte range `begin..end` of a block device.
 pub struct Mapper<T: FileSystem + ?Sized> {
-    pub inode: INode<T>,
+    pub device: Arc<dyn BlockDevice>,
     pub begin: Offset,
     pub end: Offset,
+    _p: PhantomData<*const T>,
 }
 
 unsafe impl<T: FileSystem + ?Sized> Send for Mapper<T> {}
 unsafe impl<T: FileSystem + ?Sized> Sync for Mapper<T> {}
 
+#[repr(C, align(4096))]
+struct Page([u8; PAGE_SIZE]);
+
+/// A copy of (part of) one page of a mapping.
+///
+/// The bytes start at the offset that was mapped and run to the end of its page, or to the end
+/// of the mapping if that comes first.
 pub struct Mapped {
-    data: [u8; 4096], // one page
+    data: Box<Page>,
     len: usize,
 }
 
 impl<T: FileSystem + ?Sized> Mapper<T> {
+    pub fn new(device: Arc<dyn BlockDevice>, begin: Offset, end: Offset) -> Self {
+        Self {
+            device,
+            begin,
+            end,
+            _p: PhantomData,
+        }
+    }
+
+    /// Maps the whole device.
+    pub fn for_device(device: Arc<dyn BlockDevice>) -> Result<Self> {
+        let end = device.size().try_into().map_err(|_| EFBIG)?;
+        Ok(Self::new(device, 0, end))
+    }
+
     pub fn mapped_folio(&self, offset: Offset) -> Result<Mapped> {
-        if offset < self.begin || self.end >= offset {
-            return Err(Error(34));
+        if offset < self.begin || offset >= self.end {
+            return Err(ERANGE);
         }
 
-        let map = Mapped {
-            data: [0; 4096],
-            len: 4096,
+        let page_offset = offset as usize % PAGE_SIZE;
+        let len = (PAGE_SIZE - page_offset).min((self.end - offset) as usize);
+
+        let mut map = Mapped {
+            data: Box::new(Page([0; PAGE_SIZE])),
+            len,
         };
 
+        if self.device.read_at(&mut map.data.0[..len], offset as u64)? != len {
+            return Err(EIO);
+        }
+
         Ok(map)
     }
+
+    /// Maps `offset` for writing.
+    ///
+    /// Modified bytes are written back to the device by [`MappedMut::flush`] or, failing that,
+    /// when the mapping is dropped.
+    pub fn mapped_folio_mut(&self, offset: Offset) -> Result<MappedMut> {
+        let mapped = self.mapped_folio(offset)?;
+
+        Ok(MappedMut {
+            mapped,
+            device: self.device.clone(),
+            offset: offset as u64,
+            dirty: false,
+        })
+    }
+}
+
+impl core::ops::Deref for Mapped {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data.0[..self.len]
+    }
+}
+
+/// A writable [`Mapped`] that tracks whether it has been modified.
+pub struct MappedMut {
+    mapped: Mapped,
+    device: Arc<dyn BlockDevice>,
+    offset: u64,
+    dirty: bool,
+}
+
+impl MappedMut {
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Writes the bytes back to the device if they were modified.
+    pub fn flush(&mut self) -> Result {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if self.device.write_at(&self.mapped, self.offset)? != self.mapped.len {
+            return Err(EIO);
+        }
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl core::ops::Deref for MappedMut {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.mapped
+    }
+}
+
+impl core::ops::DerefMut for MappedMut {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.dirty = true;
+        &mut self.mapped.data.0[..self.mapped.len]
+    }
+}
+
+impl Drop for MappedMut {
+    fn drop(&mut self) {
+        // Errors cannot be reported from here; callers that care use `flush` first.
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::MemDevice;
+    use crate::cred::Credentials;
+    use crate::testing::{TestFs, params, super_block};
+    use crate::uapi::S_IFREG;
+
+    #[test]
+    fn inode_cache_refcounts_and_evicts() {
+        let sb = super_block();
+
+        let INodeState::Uninitilized(new) = sb.get_or_create_inode(3).unwrap() else {
+            panic!("empty cache returned an existing inode");
+        };
+        let inode = new.init(params(0o644)).unwrap();
+        assert_eq!(inode.ino(), 3);
+        assert_eq!(sb.cached_inodes(), 1);
+
+        let INodeState::Existing(again) = sb.get_or_create_inode(3).unwrap() else {
+            panic!("cached inode was not found");
+        };
+        assert!(Arc::ptr_eq(&inode, &again));
+
+        drop(again);
+        assert_eq!(sb.cached_inodes(), 1);
+        drop(inode);
+        assert_eq!(sb.cached_inodes(), 0);
+        assert!(matches!(
+            sb.get_or_create_inode(3),
+            Ok(INodeState::Uninitilized(_))
+        ));
+    }
+
+    #[test]
+    fn operations_dispatch_and_defaults() {
+        let sb = super_block();
+
+        let INodeState::Uninitilized(mut new) = sb.get_or_create_inode(1).unwrap() else {
+            panic!("empty cache returned an existing inode");
+        };
+        new.set_iops(Ops::new::<TestFs>());
+        let inode = new.init(params(0o644)).unwrap();
+
+        let stat = inode.ops().getattr(&inode).unwrap();
+        assert_eq!((stat.ino, stat.mode, stat.nlink), (1, 0o644, 1));
+
+        let dir = inode.lock();
+        assert_eq!(inode.ops().unlink(&dir, b"x"), Err(EPERM));
+        assert!(Ops::<TestFs>::empty().getattr(&inode).is_err());
+    }
+
+    #[test]
+    fn mapped_folio_reads_device() {
+        let bytes = (0..PAGE_SIZE + 100).map(|i| i as u8).collect();
+        let mapper = Mapper::<TestFs>::for_device(Arc::new(MemDevice::from_vec(bytes))).unwrap();
+
+        let first = mapper.mapped_folio(10).unwrap();
+        assert_eq!(first.len(), PAGE_SIZE - 10);
+        assert_eq!(first[0], 10);
+
+        let last = mapper.mapped_folio(PAGE_SIZE as Offset).unwrap();
+        assert_eq!(last.len(), 100);
+        assert_eq!(last[99], (PAGE_SIZE + 99) as u8);
+
+        assert!(mapper.mapped_folio(-1).is_err());
+        assert!(mapper.mapped_folio((PAGE_SIZE + 100) as Offset).is_err());
+    }
+
+    #[test]
+    fn permissions_follow_the_mode() {
+        let sb = super_block();
+        let INodeState::Uninitilized(new) = sb.get_or_create_inode(2).unwrap() else {
+            panic!("empty cache returned an existing inode");
+        };
+        let inode = new.init(params(S_IFREG | 0o640)).unwrap();
+        inode.set_owner(1000, 100);
+
+        let check = |cred: Credentials, mask| {
+            let _cred = cred::override_creds(Arc::new(cred));
+            generic_permission(&inode, mask)
+        };
+
+        assert_eq!(
+            check(Credentials::user(1000, 1), MAY_READ | MAY_WRITE),
+            Ok(())
+        );
+        assert_eq!(check(Credentials::user(1000, 1), MAY_EXEC), Err(EACCES));
+        assert_eq!(check(Credentials::user(1, 100), MAY_READ), Ok(()));
+        assert_eq!(check(Credentials::user(1, 100), MAY_WRITE), Err(EACCES));
+        assert_eq!(
+            check(Credentials::user(1, 1).with_groups(vec![100]), MAY_READ),
+            Ok(())
+        );
+        assert_eq!(check(Credentials::user(1, 1), MAY_READ), Err(EACCES));
+
+        // Overriding does not make a file without execute bits executable.
+        assert_eq!(check(Credentials::root(), MAY_READ | MAY_WRITE), Ok(()));
+        assert_eq!(check(Credentials::root(), MAY_EXEC), Err(EACCES));
+        inode.set_mode(S_IFREG | 0o100);
+        assert_eq!(check(Credentials::root(), MAY_EXEC), Ok(()));
+    }
+
+    #[test]
+    fn setattr_needs_ownership_or_write_permission() {
+        let sb = super_block();
+        let INodeState::Uninitilized(new) = sb.get_or_create_inode(2).unwrap() else {
+            panic!("empty cache returned an existing inode");
+        };
+        let inode = new.init(params(S_IFREG | 0o664)).unwrap();
+        inode.set_owner(1000, 100);
+
+        let check = |cred: Credentials, attr: Attr| {
+            let _cred = cred::override_creds(Arc::new(cred));
+            setattr_prepare(&inode, &attr)
+        };
+        let (owner, member, other) = (
+            || Credentials::user(1000, 1),
+            || Credentials::user(1, 100),
+            || Credentials::user(1, 1),
+        );
+        let size = Attr {
+            size: Some(0),
+            ..Attr::default()
+        };
+        let mode = Attr {
+            mode: Some(0o600),
+            ..Attr::default()
+        };
+        let mtime = Attr {
+            mtime: Some(time::UNIX_EPOCH),
+            ..Attr::default()
+        };
+        let chown = |uid, gid| Attr {
+            uid: Some(uid),
+            gid: Some(gid),
+            ..Attr::default()
+        };
+
+        assert_eq!(check(member(), size), Ok(()));
+        assert_eq!(check(other(), size), Err(EACCES));
+
+        assert_eq!(check(owner(), mode), Ok(()));
+        assert_eq!(check(member(), mode), Err(EPERM));
+        assert_eq!(check(Credentials::root(), mode), Ok(()));
+        assert_eq!(check(owner(), mtime), Ok(()));
+        assert_eq!(check(member(), mtime), Err(EPERM));
+
+        assert_eq!(check(owner(), chown(1000, 100)), Ok(()));
+        assert_eq!(check(owner(), chown(1001, 100)), Err(EPERM));
+        assert_eq!(check(owner(), chown(1000, 1)), Ok(()));
+        assert_eq!(check(owner(), chown(1000, 2)), Err(EPERM));
+        assert_eq!(check(other(), chown(1000, 1)), Err(EPERM));
+        assert_eq!(check(Credentials::root(), chown(0, 0)), Ok(()));
+
+        // The filesystem is not asked to make a change that is refused.
+        let _cred = cred::override_creds(Arc::new(other()));
+        assert_eq!(inode.ops().setattr(&inode.lock(), &mode), Err(EPERM));
+    }
+
+    #[test]
+    fn mapped_folio_mut_writes_back() {
+        let dev = Arc::new(MemDevice::new(2 * PAGE_SIZE));
+        let mapper = Mapper::<TestFs>::for_device(dev.clone()).unwrap();
+
+        let mut page = mapper.mapped_folio_mut(PAGE_SIZE as Offset).unwrap();
+        assert!(!page.is_dirty());
+        page[..4].copy_from_slice(b"ezfs");
+        assert!(page.is_dirty());
+        page.flush().unwrap();
+        assert!(!page.is_dirty());
+        assert_eq!(&dev.contents()[PAGE_SIZE..PAGE_SIZE + 4], b"ezfs");
+
+        page[4] = b'!';
+        drop(page);
+        assert_eq!(dev.contents()[PAGE_SIZE + 4], b'!');
+    }
 }
@@ -1,15 +1,21 @@
+pub mod address_space;
+pub mod block;
+pub mod buffer;
+pub mod cred;
+pub mod dentry;
+pub mod file;
 pub mod fs;
+pub mod fuse;
 pub mod inode;
 pub mod sb;
-pub mod types;
-
 #[cfg(test)]
-mod tests {
-    use super::*;
+mod testing;
+pub mod time;
+pub mod transmute;
+pub mod types;
+pub mod uapi;
+pub mod user;
+pub mod vfs;
+pub mod writeback;
 
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+pub const PAGE_SIZE: usize = 4096;
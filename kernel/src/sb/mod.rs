@@ -1,11 +1,31 @@
-use std::{marker::PhantomData, num::NonZeroI32};
+use std::collections::{BTreeMap, BTreeSet};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
 
 use crate::{
-    fs::FileSystem,
+    PAGE_SIZE,
+    block::BlockDevice,
+    buffer::BufferCache,
+    dentry::{self, DEntry},
+    fs::{Context, FileSystem},
     inode::{self, INode, INodeState},
-    types::{ARef, Error, Result},
+    time::{self, Timespec},
+    types::{ARef, Result, code::EINVAL},
 };
 
+/// The filesystem is mounted read-only.
+pub const SB_RDONLY: u32 = 1 << 0;
+/// Access times are not updated.
+pub const SB_NOATIME: u32 = 1 << 10;
+
+/// The flags that mount options and remounting can change.
+const SB_RMT_MASK: u32 = SB_RDONLY | SB_NOATIME;
+
+/// The largest file size a filesystem supports unless it says otherwise, the equivalent of
+/// `MAX_NON_LFS`.
+pub const MAX_NON_LFS: u64 = (1 << 31) - 1;
+
 pub trait DataInited {}
 
 pub enum Type {
@@ -19,57 +39,548 @@ pub enum Ready {}
 
 impl DataInited for Ready {}
 
-pub struct Inner {}
+/// State shared by all handles to a superblock and by the inodes that belong to it.
+pub struct Inner<T: FileSystem + ?Sized> {
+    magic: AtomicUsize,
+    /// `SB_*` flags.
+    flags: AtomicU32,
+    blocksize: AtomicU32,
+    /// Largest file size, the equivalent of `s_maxbytes`.
+    maxbytes: AtomicU64,
+    /// Granularity of the stored times in nanoseconds, the equivalent of `s_time_gran`.
+    time_gran: AtomicU32,
+    /// Set by [`SuperBlock::ready`] from the value returned by [`FileSystem::fill_super`].
+    data: OnceLock<T::Data>,
+    device: Option<Arc<dyn BlockDevice>>,
+    /// Buffers of `device` in use.
+    buffers: BufferCache,
+    /// Set by [`SuperBlock::set_ops`] while the superblock is being filled.
+    ops: Mutex<Ops<T>>,
+    /// Inode cache, keyed by inode number. Entries do not keep inodes alive.
+    inodes: Mutex<BTreeMap<usize, Weak<INode<T>>>>,
+    /// Numbers of the inodes marked dirty since the last writeback, the equivalent of `b_dirty`.
+    dirty: Mutex<BTreeSet<usize>>,
+    /// Root of the dentry tree, installed at mount time and dropped by [`kill_sb`].
+    ///
+    /// [`kill_sb`]: crate::fs::kill_sb
+    root: Mutex<Option<dentry::Root<T>>>,
+}
 
-pub struct SuperBlock<T: FileSystem + ?Sized, S> {
-    magic: usize,
-    data: T::Data,
-    _p: PhantomData<(S, T)>,
+pub struct SuperBlock<T: FileSystem + ?Sized, S = Ready> {
+    inner: Arc<Inner<T>>,
+    _p: PhantomData<S>,
 }
 
 impl<T: FileSystem> SuperBlock<T, New> {
-    pub fn new(data: T::Data) -> Self {
+    pub fn new(device: Option<Arc<dyn BlockDevice>>) -> Self {
         SuperBlock {
-            magic: 0,
-            data,
+            inner: Arc::new(Inner {
+                magic: AtomicUsize::new(0),
+                flags: AtomicU32::new(0),
+                blocksize: AtomicU32::new(PAGE_SIZE as u32),
+                maxbytes: AtomicU64::new(MAX_NON_LFS),
+                time_gran: AtomicU32::new(time::NSEC_PER_SEC),
+                data: OnceLock::new(),
+                device,
+                buffers: BufferCache::default(),
+                ops: Mutex::new(Ops::empty()),
+                inodes: Mutex::new(BTreeMap::new()),
+                dirty: Mutex::new(BTreeSet::new()),
+                root: Mutex::new(None),
+            }),
             _p: PhantomData,
         }
     }
 
-    pub fn magic(&self) -> usize {
-        self.magic
+    pub fn set_magic(&mut self, magic: usize) -> &mut Self {
+        self.inner.magic.store(magic, Ordering::Relaxed);
+
+        self
     }
 
-    pub fn set_magic(&mut self, magic: usize) -> &mut Self {
-        self.magic = magic;
+    /// Sets the `SB_*` flags; mounting sets them from the mount options before
+    /// [`FileSystem::fill_super`] runs.
+    pub fn set_flags(&mut self, flags: u32) -> &mut Self {
+        self.inner.flags.store(flags, Ordering::Relaxed);
+
+        self
+    }
+
+    pub fn set_rdonly(&mut self, rdonly: bool) -> &mut Self {
+        if rdonly {
+            self.inner.flags.fetch_or(SB_RDONLY, Ordering::Relaxed);
+        } else {
+            self.inner.flags.fetch_and(!SB_RDONLY, Ordering::Relaxed);
+        }
 
         self
     }
 
-    pub fn ready(self) -> SuperBlock<T, Ready> {
+    /// Sets the block size, which must be a power of two between 512 and [`PAGE_SIZE`].
+    pub fn set_blocksize(&mut self, size: u32) -> Result<&mut Self> {
+        if !size.is_power_of_two() || !(512..=PAGE_SIZE as u32).contains(&size) {
+            return Err(EINVAL);
+        }
+
+        self.inner.blocksize.store(size, Ordering::Relaxed);
+        Ok(self)
+    }
+
+    pub fn set_maxbytes(&mut self, maxbytes: u64) -> &mut Self {
+        self.inner.maxbytes.store(maxbytes, Ordering::Relaxed);
+
+        self
+    }
+
+    /// Sets the granularity of stored times, which must be between one nanosecond and one
+    /// second.
+    pub fn set_time_gran(&mut self, gran: u32) -> Result<&mut Self> {
+        if !(1..=time::NSEC_PER_SEC).contains(&gran) {
+            return Err(EINVAL);
+        }
+
+        self.inner.time_gran.store(gran, Ordering::Relaxed);
+        Ok(self)
+    }
+
+    pub fn set_ops(&mut self, ops: Ops<T>) -> &mut Self {
+        *self.inner.ops.lock().unwrap_or_else(|e| e.into_inner()) = ops;
+
+        self
+    }
+
+    /// Installs the filesystem data, after which inodes can be created.
+    pub fn ready(self, data: T::Data) -> SuperBlock<T, Ready> {
+        if self.inner.data.set(data).is_err() {
+            unreachable!("only `ready` sets the data, and it consumes the superblock");
+        }
+
         SuperBlock {
-            magic: self.magic,
-            data: self.data,
+            inner: self.inner,
             _p: PhantomData,
         }
     }
 }
 
-impl<T: FileSystem, S: DataInited> SuperBlock<T, S> {
-    pub fn get_or_create_inode(ino: usize) -> Result<INodeState<T>> {
-        // TODO: Add kani
-        // let exists: bool = kani::any();
-        let exists = false;
-
-        if exists {
-            // TODO: Add true case
-            // let inode = INode::new(ino, Some(kani::any()));
-            // FIXME:
-            Err(Error(1))
-        } else {
-            // Return uninitialized inode
-            let inode = inode::New::new(ino, None);
-            Ok(INodeState::Uninitilized(inode))
+impl<T: FileSystem + ?Sized, S> SuperBlock<T, S> {
+    pub fn magic(&self) -> usize {
+        self.inner.magic.load(Ordering::Relaxed)
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.inner.flags.load(Ordering::Relaxed)
+    }
+
+    pub fn is_rdonly(&self) -> bool {
+        self.flags() & SB_RDONLY != 0
+    }
+
+    pub fn blocksize(&self) -> u32 {
+        self.inner.blocksize.load(Ordering::Relaxed)
+    }
+
+    pub fn blocksize_bits(&self) -> u32 {
+        self.blocksize().trailing_zeros()
+    }
+
+    pub fn maxbytes(&self) -> u64 {
+        self.inner.maxbytes.load(Ordering::Relaxed)
+    }
+
+    pub fn time_gran(&self) -> u32 {
+        self.inner.time_gran.load(Ordering::Relaxed)
+    }
+
+    /// Truncates `time` to the granularity of the filesystem, the equivalent of
+    /// `timestamp_truncate`.
+    pub fn timestamp_truncate(&self, time: Timespec) -> Timespec {
+        time.truncate(self.time_gran())
+    }
+
+    /// Replaces the flags that mount options control with those in `flags`.
+    pub(crate) fn update_flags(&self, flags: u32) {
+        let old = self.flags();
+        let new = (old & !SB_RMT_MASK) | (flags & SB_RMT_MASK);
+
+        self.inner.flags.store(new, Ordering::Relaxed);
+    }
+
+    /// Makes the filesystem read-only until it is remounted, as filesystems do on finding the
+    /// disk corrupted. Whatever is still dirty is no longer written back.
+    pub fn force_rdonly(&self) {
+        self.inner.flags.fetch_or(SB_RDONLY, Ordering::Relaxed);
+    }
+
+    /// The block device the filesystem was mounted from, if any.
+    pub fn device(&self) -> Option<&Arc<dyn BlockDevice>> {
+        self.inner.device.as_ref()
+    }
+
+    pub fn ops(&self) -> Ops<T> {
+        *self.inner.ops.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub(crate) fn buffers(&self) -> &BufferCache {
+        &self.inner.buffers
+    }
+}
+
+impl<T: FileSystem + ?Sized, S: DataInited> SuperBlock<T, S> {
+    pub fn data(&self) -> &T::Data {
+        match self.inner.data.get() {
+            Some(data) => data,
+            None => unreachable!("a `DataInited` superblock has its data set"),
+        }
+    }
+
+    /// The root dentry, until the superblock is killed.
+    pub fn root(&self) -> Option<ARef<DEntry<T>>> {
+        self.inner
+            .root
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .map(|root| root.dentry().clone())
+    }
+
+    pub(crate) fn set_root(&self, root: Option<dentry::Root<T>>) -> Option<dentry::Root<T>> {
+        core::mem::replace(
+            &mut *self.inner.root.lock().unwrap_or_else(|e| e.into_inner()),
+            root,
+        )
+    }
+
+    /// Returns the cached inode `ino`, or a new inode for the filesystem to initialise.
+    ///
+    /// This is the equivalent of `iget_locked`: the returned reference counts as a use of the
+    /// inode, and the inode is evicted once the last reference is dropped.
+    ///
+    /// Under Kani, an inode that is not cached nondeterministically is, with arbitrary contents
+    /// from [`FileSystem::kani_existing_inode`], so that harnesses cover both paths.
+    pub fn get_or_create_inode(&self, ino: usize) -> Result<INodeState<T>>
+    where
+        T: Sized,
+    {
+        let inodes = self.inner.inodes.lock()?;
+
+        if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+            return Ok(INodeState::Existing(inode));
         }
+
+        // Creating an inode takes the lock again.
+        drop(inodes);
+
+        #[cfg(kani)]
+        if kani::any() {
+            let inode = T::kani_existing_inode(inode::New::new(self.handle(), ino))?;
+            return Ok(INodeState::Existing(inode));
+        }
+
+        self.ops().alloc_inode(&self.handle())?;
+
+        // Return uninitialized inode
+        let inode = inode::New::new(self.handle(), ino);
+        Ok(INodeState::Uninitilized(inode))
+    }
+
+    /// Number of inodes currently in the cache.
+    pub fn cached_inodes(&self) -> usize {
+        self.inner
+            .inodes
+            .lock()
+            .map(|inodes| inodes.len())
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn handle(&self) -> SuperBlock<T, Ready> {
+        SuperBlock {
+            inner: self.inner.clone(),
+            _p: PhantomData,
+        }
+    }
+
+    /// Adds `inode` to the cache, or returns the inode that was cached for its number in the
+    /// meantime.
+    pub(crate) fn insert_inode(&self, inode: INode<T>) -> Result<ARef<INode<T>>>
+    where
+        T: Sized,
+    {
+        let mut inodes = self.inner.inodes.lock()?;
+
+        if let Some(existing) = inodes.get(&inode.ino()).and_then(Weak::upgrade) {
+            // Dropping `inode` takes the lock again.
+            drop(inodes);
+            drop(inode);
+            return Ok(existing);
+        }
+
+        let inode = Arc::new(inode);
+        inodes.insert(inode.ino(), Arc::downgrade(&inode));
+
+        Ok(inode)
+    }
+
+    pub(crate) fn queue_dirty_inode(&self, ino: usize) {
+        self.inner
+            .dirty
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(ino);
+    }
+
+    /// Empties the dirty list, returning the inodes on it that are still cached.
+    pub(crate) fn take_dirty_inodes(&self) -> Result<Vec<ARef<INode<T>>>> {
+        let dirty = core::mem::take(&mut *self.inner.dirty.lock()?);
+        let inodes = self.inner.inodes.lock()?;
+
+        Ok(dirty
+            .into_iter()
+            .filter_map(|ino| inodes.get(&ino).and_then(Weak::upgrade))
+            .collect())
+    }
+
+    /// Number of inodes on the dirty list.
+    pub fn dirty_inodes(&self) -> usize {
+        self.inner
+            .dirty
+            .lock()
+            .map(|dirty| dirty.len())
+            .unwrap_or(0)
+    }
+
+    /// Drops the cache entry for `ino` if it no longer refers to a live inode.
+    pub(crate) fn remove_inode(&self, ino: usize) {
+        if let Ok(mut inodes) = self.inner.inodes.lock()
+            && inodes.get(&ino).is_some_and(|w| w.strong_count() == 0)
+        {
+            inodes.remove(&ino);
+
+            if let Ok(mut dirty) = self.inner.dirty.lock() {
+                dirty.remove(&ino);
+            }
+        }
+    }
+}
+
+/// Filesystem statistics reported by [`Operations::statfs`], the equivalent of `struct kstatfs`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatFs {
+    pub magic: usize,
+    pub bsize: u64,
+    pub blocks: u64,
+    pub bfree: u64,
+    pub bavail: u64,
+    pub files: u64,
+    pub ffree: u64,
+    pub namelen: u64,
+}
+
+/// Superblock operations, the equivalent of `struct super_operations`.
+///
+/// Every operation defaults to doing nothing and succeeding.
+pub trait Operations {
+    type FileSystem: FileSystem + ?Sized;
+
+    /// Called before a new inode is handed to the filesystem to initialise; failing stops it
+    /// from being created.
+    fn alloc_inode(_sb: &SuperBlock<Self::FileSystem>) -> Result {
+        Ok(())
+    }
+
+    /// Called when an inode is freed, after [`Operations::evict_inode`].
+    fn destroy_inode(_inode: &INode<Self::FileSystem>) {}
+
+    /// Writes the attributes of `inode` back to the disk; `wait` asks for the write to complete
+    /// before returning.
+    fn write_inode(_inode: &INode<Self::FileSystem>, _wait: bool) -> Result {
+        Ok(())
+    }
+
+    /// Called when the last reference to `inode` is dropped, after it has been written back.
+    ///
+    /// This is where the filesystem releases the storage of inodes that have no links left.
+    fn evict_inode(_inode: &INode<Self::FileSystem>) {}
+
+    /// Called by [`kill_sb`] once everything has been written back.
+    ///
+    /// [`kill_sb`]: crate::fs::kill_sb
+    fn put_super(_sb: &SuperBlock<Self::FileSystem>) {}
+
+    /// Writes back filesystem-wide state, such as allocation bitmaps, after the inodes have been
+    /// written; `wait` asks for the writes to complete before returning.
+    fn sync_fs(_sb: &SuperBlock<Self::FileSystem>, _wait: bool) -> Result {
+        Ok(())
+    }
+
+    fn statfs(sb: &SuperBlock<Self::FileSystem>) -> Result<StatFs> {
+        Ok(StatFs {
+            magic: sb.magic(),
+            bsize: PAGE_SIZE as u64,
+            namelen: 255,
+            ..StatFs::default()
+        })
+    }
+
+    /// Applies the options in `ctx` to a mounted filesystem.
+    fn remount_fs(_sb: &SuperBlock<Self::FileSystem>, _ctx: &Context) -> Result {
+        Ok(())
+    }
+
+    /// Appends the filesystem-specific mount options to `out`, each preceded by a comma.
+    fn show_options(_sb: &SuperBlock<Self::FileSystem>, _out: &mut String) -> Result {
+        Ok(())
+    }
+}
+
+/// A table of superblock operations, created from an [`Operations`] implementation.
+pub struct Ops<T: FileSystem + ?Sized>(&'static Table<T>);
+
+impl<T: FileSystem + ?Sized> Clone for Ops<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: FileSystem + ?Sized> Copy for Ops<T> {}
+
+struct Table<T: FileSystem + ?Sized> {
+    alloc_inode: fn(&SuperBlock<T>) -> Result,
+    destroy_inode: fn(&INode<T>),
+    write_inode: fn(&INode<T>, bool) -> Result,
+    evict_inode: fn(&INode<T>),
+    put_super: fn(&SuperBlock<T>),
+    sync_fs: fn(&SuperBlock<T>, bool) -> Result,
+    statfs: fn(&SuperBlock<T>) -> Result<StatFs>,
+    remount_fs: fn(&SuperBlock<T>, &Context) -> Result,
+    show_options: fn(&SuperBlock<T>, &mut String) -> Result,
+}
+
+struct TableFor<U: ?Sized>(PhantomData<U>);
+
+impl<U: Operations + ?Sized> TableFor<U> {
+    const TABLE: Table<U::FileSystem> = Table {
+        alloc_inode: U::alloc_inode,
+        destroy_inode: U::destroy_inode,
+        write_inode: U::write_inode,
+        evict_inode: U::evict_inode,
+        put_super: U::put_super,
+        sync_fs: U::sync_fs,
+        statfs: U::statfs,
+        remount_fs: U::remount_fs,
+        show_options: U::show_options,
+    };
+}
+
+/// Operations for filesystems that were not given any.
+struct NoOps<T: ?Sized>(PhantomData<T>);
+
+impl<T: FileSystem + ?Sized> Operations for NoOps<T> {
+    type FileSystem = T;
+}
+
+impl<T: FileSystem + ?Sized> Ops<T> {
+    pub const fn new<U: Operations<FileSystem = T> + ?Sized>() -> Self {
+        Self(&TableFor::<U>::TABLE)
+    }
+
+    pub const fn empty() -> Self {
+        Self::new::<NoOps<T>>()
+    }
+
+    pub fn alloc_inode(&self, sb: &SuperBlock<T>) -> Result {
+        (self.0.alloc_inode)(sb)
+    }
+
+    pub fn destroy_inode(&self, inode: &INode<T>) {
+        (self.0.destroy_inode)(inode)
+    }
+
+    pub fn write_inode(&self, inode: &INode<T>, wait: bool) -> Result {
+        (self.0.write_inode)(inode, wait)
+    }
+
+    pub fn evict_inode(&self, inode: &INode<T>) {
+        (self.0.evict_inode)(inode)
+    }
+
+    pub fn put_super(&self, sb: &SuperBlock<T>) {
+        (self.0.put_super)(sb)
+    }
+
+    pub fn sync_fs(&self, sb: &SuperBlock<T>, wait: bool) -> Result {
+        (self.0.sync_fs)(sb, wait)
+    }
+
+    pub fn statfs(&self, sb: &SuperBlock<T>) -> Result<StatFs> {
+        (self.0.statfs)(sb)
+    }
+
+    pub fn remount_fs(&self, sb: &SuperBlock<T>, ctx: &Context) -> Result {
+        (self.0.remount_fs)(sb, ctx)
+    }
+
+    pub fn show_options(&self, sb: &SuperBlock<T>, out: &mut String) -> Result {
+        (self.0.show_options)(sb, out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs;
+    use crate::testing::{TestFs, super_block};
+
+    #[test]
+    fn geometry_is_set_while_new() {
+        let mut sb = SuperBlock::<TestFs, New>::new(None);
+
+        assert_eq!(sb.set_blocksize(1000).err(), Some(EINVAL));
+        assert_eq!(sb.set_blocksize(8192).err(), Some(EINVAL));
+        assert_eq!(sb.set_time_gran(0).err(), Some(EINVAL));
+
+        sb.set_blocksize(1024)
+            .unwrap()
+            .set_maxbytes(1 << 20)
+            .set_rdonly(true);
+        sb.set_time_gran(1000).unwrap();
+
+        let sb = sb.ready(Default::default());
+        assert_eq!((sb.blocksize(), sb.blocksize_bits()), (1024, 10));
+        assert_eq!(sb.maxbytes(), 1 << 20);
+        assert!(sb.is_rdonly());
+
+        let time = Timespec::new(5, 123_456_789).unwrap();
+        assert_eq!(
+            sb.timestamp_truncate(time),
+            Timespec::new(5, 123_456_000).unwrap()
+        );
+
+        sb.update_flags(SB_NOATIME);
+        assert_eq!(sb.flags(), SB_NOATIME);
+        sb.force_rdonly();
+        assert_eq!(sb.flags(), SB_NOATIME | SB_RDONLY);
+    }
+
+    #[test]
+    fn operations_default_to_no_ops() {
+        let sb = super_block();
+        let ops = Ops::<TestFs>::empty();
+
+        let stat = ops.statfs(&sb).unwrap();
+        assert_eq!((stat.bsize, stat.namelen, stat.blocks), (4096, 255, 0));
+        assert_eq!(ops.sync_fs(&sb, true), Ok(()));
+        assert_eq!(ops.remount_fs(&sb, &Context::new()), Ok(()));
+        assert_eq!(fs::show_options(&sb).unwrap(), "rw");
+
+        assert_eq!((sb.blocksize(), sb.blocksize_bits()), (4096, 12));
+        assert_eq!(
+            (sb.maxbytes(), sb.time_gran()),
+            (MAX_NON_LFS, 1_000_000_000)
+        );
+        assert!(!sb.is_rdonly());
+
+        // TestFs only writes inodes; the rest falls back to the defaults.
+        assert_eq!(fs::statfs(&sb).unwrap(), stat);
+        fs::kill_sb(sb).unwrap();
     }
 }
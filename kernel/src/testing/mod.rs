@@ -0,0 +1,163 @@
+//! A minimal filesystem for the unit tests of this crate.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::PAGE_SIZE;
+use crate::address_space::{self, Folio};
+use crate::dentry::{self, DEntry};
+use crate::file::File;
+use crate::fs::{Context, FileSystem, ParamSpec};
+use crate::inode::{self, INode, INodeState, Mapper, Ops, Params, ReadSem, Stat};
+use crate::sb::{self, Ready, SuperBlock};
+use crate::time::UNIX_EPOCH;
+use crate::types::{ARef, Locked, Result};
+use crate::uapi::{S_IFDIR, S_IFREG, mode_t};
+
+pub(crate) const OPT_SIZE: u32 = 0;
+pub(crate) const OPT_DEBUG: u32 = 1;
+pub(crate) const OPT_MODE: u32 = 2;
+pub(crate) const OPT_NAME: u32 = 3;
+
+/// A filesystem whose directories contain every name except `missing`; names starting with `d`
+/// are directories.
+pub(crate) struct TestFs;
+
+impl FileSystem for TestFs {
+    /// Number of lookups that reached the filesystem.
+    type Data = AtomicUsize;
+    type INodeData = ();
+    const NAME: &str = "testfs";
+    const PARAMETERS: &[ParamSpec] = &[
+        ParamSpec::u32("size", OPT_SIZE),
+        ParamSpec::flag("debug", OPT_DEBUG),
+        ParamSpec::enumeration("mode", OPT_MODE, &[("safe", 0), ("fast", 1)]),
+        ParamSpec::string("name", OPT_NAME),
+    ];
+
+    fn fill_super(
+        sb: &mut SuperBlock<Self, sb::New>,
+        _: &Context,
+        _: Option<Mapper<Self>>,
+    ) -> Result<AtomicUsize> {
+        sb.set_ops(sb::Ops::new::<TestFs>());
+        Ok(AtomicUsize::new(0))
+    }
+
+    fn init_root(sb: &SuperBlock<Self>) -> Result<dentry::Root<Self>> {
+        dentry::Root::try_new(get_inode(sb, 1, S_IFDIR | 0o755))
+    }
+}
+
+impl sb::Operations for TestFs {
+    type FileSystem = Self;
+
+    fn write_inode(_: &INode<Self>, _: bool) -> Result {
+        INODES_WRITTEN.with(|n| n.set(n.get() + 1));
+        Ok(())
+    }
+}
+
+impl inode::Operations for TestFs {
+    type FileSystem = Self;
+
+    fn lookup(
+        parent: &Locked<&INode<Self>, ReadSem>,
+        dentry: dentry::Unhashed<'_, Self>,
+    ) -> Result<Option<ARef<DEntry<Self>>>> {
+        let sb = parent.super_block();
+        sb.data().fetch_add(1, Ordering::Relaxed);
+
+        let name = dentry.name();
+        if name == b"missing" {
+            return dentry.splice_alias(None);
+        }
+
+        let ino = name.iter().fold(parent.ino(), |h, &b| {
+            h.wrapping_mul(31).wrapping_add(b.into())
+        });
+        let mode = if name.starts_with(b"d") {
+            S_IFDIR | 0o755
+        } else {
+            S_IFREG | 0o644
+        };
+
+        dentry.splice_alias(Some(get_inode(sb, ino, mode)))
+    }
+
+    fn getattr(inode: &INode<Self>) -> Result<Stat> {
+        Ok(inode::generic_fillattr(inode))
+    }
+}
+
+thread_local! {
+    /// Number of pages read through [`TestFs`] on this thread.
+    pub(crate) static PAGES_READ: Cell<usize> = const { Cell::new(0) };
+
+    /// Number of inodes written back through [`TestFs`] on this thread.
+    pub(crate) static INODES_WRITTEN: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Regular files read as pages filled with their index and cannot be written back.
+impl address_space::Operations for TestFs {
+    type FileSystem = Self;
+
+    fn read_folio(_: &INode<Self>, folio: &Folio, data: &mut [u8; PAGE_SIZE]) -> Result {
+        PAGES_READ.with(|n| n.set(n.get() + 1));
+        data.fill(folio.index() as u8);
+        Ok(())
+    }
+}
+
+pub(crate) fn params(mode: mode_t) -> Params<()> {
+    Params {
+        mode,
+        size: 0,
+        blocks: 0,
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        atime: UNIX_EPOCH,
+        mtime: UNIX_EPOCH,
+        ctime: UNIX_EPOCH,
+        value: (),
+    }
+}
+
+pub(crate) fn super_block() -> SuperBlock<TestFs, Ready> {
+    let mut sb = SuperBlock::<TestFs, sb::New>::new(None);
+    sb.set_ops(sb::Ops::new::<TestFs>());
+    sb.ready(AtomicUsize::new(0))
+}
+
+pub(crate) fn get_inode(
+    sb: &SuperBlock<TestFs, Ready>,
+    ino: usize,
+    mode: mode_t,
+) -> ARef<INode<TestFs>> {
+    match sb.get_or_create_inode(ino).unwrap() {
+        INodeState::Existing(inode) => inode,
+        INodeState::Uninitilized(mut new) => {
+            new.set_iops(Ops::new::<TestFs>());
+            new.init(params(mode)).unwrap()
+        }
+    }
+}
+
+pub(crate) fn new_inode(sb: &SuperBlock<TestFs, Ready>, ino: usize) -> ARef<INode<TestFs>> {
+    let INodeState::Uninitilized(new) = sb.get_or_create_inode(ino).unwrap() else {
+        panic!("inode {ino} is already cached");
+    };
+
+    new.init(params(S_IFREG | 0o644)).unwrap()
+}
+
+/// Opens a new regular file backed by the page cache.
+pub(crate) fn new_file(sb: &SuperBlock<TestFs, Ready>, ino: usize) -> File<TestFs> {
+    let INodeState::Uninitilized(mut new) = sb.get_or_create_inode(ino).unwrap() else {
+        panic!("inode {ino} is already cached");
+    };
+
+    new.set_aops(address_space::Ops::new::<TestFs>());
+    File::new(new.init(params(S_IFREG | 0o644)).unwrap())
+}
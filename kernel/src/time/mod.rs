@@ -0,0 +1,128 @@
+use std::cell::Cell;
+
+use crate::types::Result;
+use crate::types::code::EINVAL;
+
+pub const NSEC_PER_SEC: u32 = 1_000_000_000;
+
+/// A point in time, in seconds and nanoseconds since the Unix epoch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timespec {
+    sec: i64,
+    nsec: u32,
+}
+
+pub const UNIX_EPOCH: Timespec = Timespec { sec: 0, nsec: 0 };
+
+impl Timespec {
+    /// Fails with `EINVAL` if `nsec` is not below one second or `sec` does not fit in an `i64`.
+    pub fn new(sec: u64, nsec: u32) -> Result<Self> {
+        if nsec >= NSEC_PER_SEC {
+            return Err(EINVAL);
+        }
+
+        Ok(Self {
+            sec: sec.try_into()?,
+            nsec,
+        })
+    }
+
+    pub fn sec(&self) -> i64 {
+        self.sec
+    }
+
+    pub fn nsec(&self) -> u32 {
+        self.nsec
+    }
+
+    /// Rounds down to a multiple of `gran` nanoseconds; granularities of zero or above one second
+    /// are treated as one second.
+    pub fn truncate(self, gran: u32) -> Self {
+        let gran = if gran == 0 {
+            NSEC_PER_SEC
+        } else {
+            gran.min(NSEC_PER_SEC)
+        };
+
+        Self {
+            sec: self.sec,
+            nsec: self.nsec - self.nsec % gran,
+        }
+    }
+}
+
+#[cfg(kani)]
+impl kani::Arbitrary for Timespec {
+    fn any() -> Self {
+        let nsec: u32 = kani::any();
+        kani::assume(nsec < NSEC_PER_SEC);
+
+        Self {
+            sec: kani::any(),
+            nsec,
+        }
+    }
+}
+
+thread_local! {
+    static CLOCK: Cell<Option<Timespec>> = const { Cell::new(None) };
+}
+
+/// Fixes the time returned by [`current_time`] on this thread, or with `None` returns to the
+/// system clock.
+pub fn set_clock(time: Option<Timespec>) {
+    CLOCK.with(|clock| clock.set(time));
+}
+
+/// The current time, the equivalent of `current_time`.
+///
+/// Without a clock set through [`set_clock`], this reads the system clock, except under Kani,
+/// where it is [`UNIX_EPOCH`].
+pub fn current_time() -> Timespec {
+    if let Some(time) = CLOCK.with(Cell::get) {
+        return time;
+    }
+
+    #[cfg(kani)]
+    {
+        UNIX_EPOCH
+    }
+
+    #[cfg(not(kani))]
+    {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Timespec::new(now.as_secs(), now.subsec_nanos()).unwrap_or(UNIX_EPOCH)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timespec_bounds() {
+        assert_eq!(Timespec::new(1, 999_999_999).unwrap().nsec(), 999_999_999);
+        assert!(Timespec::new(1, NSEC_PER_SEC).is_err());
+        assert!(Timespec::new(u64::MAX, 0).is_err());
+        assert!(UNIX_EPOCH < Timespec::new(0, 1).unwrap());
+
+        let time = Timespec::new(7, 987_654_321).unwrap();
+        assert_eq!(time.truncate(1), time);
+        assert_eq!(time.truncate(1000).nsec(), 987_654_000);
+        assert_eq!(time.truncate(NSEC_PER_SEC), Timespec::new(7, 0).unwrap());
+    }
+
+    #[test]
+    fn clock_is_settable() {
+        let time = Timespec::new(1234, 5).unwrap();
+
+        set_clock(Some(time));
+        assert_eq!(current_time(), time);
+
+        set_clock(None);
+        assert!(current_time() > time);
+    }
+}
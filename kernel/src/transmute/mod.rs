@@ -0,0 +1,122 @@
+use core::mem::size_of;
+
+/// Types for which any bit pattern is a valid value.
+///
+/// # Safety
+///
+/// Implementers must ensure that every possible sequence of `size_of::<Self>()` bytes is a valid
+/// instance of `Self`.
+pub unsafe trait FromBytes {
+    /// Views `bytes` as a `Self`.
+    ///
+    /// Returns `None` if `bytes` is not exactly `size_of::<Self>()` long or is not suitably
+    /// aligned.
+    fn from_bytes(bytes: &[u8]) -> Option<&Self>
+    where
+        Self: Sized,
+    {
+        if bytes.len() != size_of::<Self>() || !is_aligned::<Self>(bytes.as_ptr()) {
+            return None;
+        }
+
+        // SAFETY: `bytes` is large enough and suitably aligned, and any bit pattern is a valid
+        // `Self` by the safety requirements of the trait.
+        Some(unsafe { &*bytes.as_ptr().cast::<Self>() })
+    }
+
+    /// Views `bytes` as a mutable `Self`, with the same checks as [`FromBytes::from_bytes`].
+    fn from_bytes_mut(bytes: &mut [u8]) -> Option<&mut Self>
+    where
+        Self: AsBytes + Sized,
+    {
+        if bytes.len() != size_of::<Self>() || !is_aligned::<Self>(bytes.as_ptr()) {
+            return None;
+        }
+
+        // SAFETY: As above. `Self: AsBytes` guarantees that any value written through the
+        // returned reference is still a valid sequence of initialized bytes.
+        Some(unsafe { &mut *bytes.as_mut_ptr().cast::<Self>() })
+    }
+}
+
+/// Types that can be viewed as a sequence of initialized bytes.
+///
+/// # Safety
+///
+/// Implementers must ensure that `Self` contains no padding and no interior mutability, so that
+/// all of its `size_of::<Self>()` bytes are always initialized.
+pub unsafe trait AsBytes {
+    fn as_bytes(&self) -> &[u8]
+    where
+        Self: Sized,
+    {
+        // SAFETY: All bytes of `Self` are initialized by the safety requirements of the trait.
+        unsafe {
+            core::slice::from_raw_parts((self as *const Self).cast::<u8>(), size_of::<Self>())
+        }
+    }
+
+    fn as_bytes_mut(&mut self) -> &mut [u8]
+    where
+        Self: FromBytes + Sized,
+    {
+        // SAFETY: All bytes of `Self` are initialized, and since `Self: FromBytes` any bytes
+        // written through the slice leave a valid `Self` behind.
+        unsafe {
+            core::slice::from_raw_parts_mut((self as *mut Self).cast::<u8>(), size_of::<Self>())
+        }
+    }
+}
+
+fn is_aligned<T>(ptr: *const u8) -> bool {
+    ptr.cast::<T>().is_aligned()
+}
+
+macro_rules! impl_transmute {
+    ($($t:ty),*) => {
+        $(
+            // SAFETY: Primitive integers have no padding and are valid for any bit pattern.
+            unsafe impl FromBytes for $t {}
+            // SAFETY: See above.
+            unsafe impl AsBytes for $t {}
+        )*
+    };
+}
+
+impl_transmute!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize
+);
+
+// SAFETY: An array has no padding between its elements, so it is valid for any bit pattern if
+// its element type is.
+unsafe impl<T: FromBytes, const N: usize> FromBytes for [T; N] {}
+
+// SAFETY: An array has no padding between its elements, so its bytes are initialized if those of
+// its element type are.
+unsafe impl<T: AsBytes, const N: usize> AsBytes for [T; N] {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C, align(8))]
+    struct Aligned([u8; 16]);
+
+    #[test]
+    fn from_bytes_checks_size_and_alignment() {
+        let buf = Aligned([1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert_eq!(<[u64; 2]>::from_bytes(&buf.0), Some(&[1, 2]));
+        assert_eq!(u64::from_bytes(&buf.0), None);
+        assert_eq!(u64::from_bytes(&buf.0[1..9]), None);
+    }
+
+    #[test]
+    fn as_bytes_round_trip() {
+        let mut value = [0u32; 2];
+        value.as_bytes_mut()[4] = 7;
+
+        assert_eq!(value, [0, 7]);
+        assert_eq!(value.as_bytes(), &[0, 0, 0, 0, 7, 0, 0, 0]);
+    }
+}
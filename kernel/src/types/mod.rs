@@ -1,15 +1,113 @@
-use core::num::NonZeroI32;
+use std::fmt;
 use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
 
-pub type ARef<T> = Box<T>;
+pub mod code;
 
-pub struct Locked<T, L> {
+/// A reference-counted pointer to an object such as an inode.
+pub type ARef<T> = Arc<T>;
+
+/// A reader-writer semaphore, the equivalent of `struct rw_semaphore`.
+///
+/// It is only taken through [`Locked`], which releases it when dropped.
+#[derive(Default)]
+pub struct RwSemaphore {
+    /// Number of readers holding the semaphore, or `-1` while a writer holds it.
+    count: Mutex<isize>,
+    released: Condvar,
+}
+
+impl RwSemaphore {
+    pub const fn new() -> Self {
+        Self {
+            count: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self, ready: impl Fn(isize) -> bool, take: impl FnOnce(&mut isize)) {
+        let mut count = self.count.lock().unwrap_or_else(|e| e.into_inner());
+
+        while !ready(*count) {
+            count = self.released.wait(count).unwrap_or_else(|e| e.into_inner());
+        }
+
+        take(&mut count);
+    }
+
+    fn release(&self, put: impl FnOnce(&mut isize)) {
+        put(&mut self.count.lock().unwrap_or_else(|e| e.into_inner()));
+        self.released.notify_all();
+    }
+}
+
+/// An object protected by a [`RwSemaphore`], such as an inode.
+pub trait Lockable {
+    fn rwsem(&self) -> &RwSemaphore;
+}
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// How a [`Locked`] holds its semaphore: [`ReadSem`] or [`WriteSem`].
+pub trait LockKind: private::Sealed {
+    #[doc(hidden)]
+    fn acquire(sem: &RwSemaphore);
+
+    #[doc(hidden)]
+    fn release(sem: &RwSemaphore);
+}
+
+/// Shared access, the equivalent of `down_read`.
+pub struct ReadSem;
+
+/// Exclusive access, the equivalent of `down_write`.
+pub struct WriteSem;
+
+impl private::Sealed for ReadSem {}
+impl private::Sealed for WriteSem {}
+
+impl LockKind for ReadSem {
+    fn acquire(sem: &RwSemaphore) {
+        sem.acquire(|count| count >= 0, |count| *count += 1);
+    }
+
+    fn release(sem: &RwSemaphore) {
+        sem.release(|count| *count -= 1);
+    }
+}
+
+impl LockKind for WriteSem {
+    fn acquire(sem: &RwSemaphore) {
+        sem.acquire(|count| count == 0, |count| *count = -1);
+    }
+
+    fn release(sem: &RwSemaphore) {
+        sem.release(|count| *count = 0);
+    }
+}
+
+/// A reference to `T::Target` with its semaphore held as `L`, released on drop.
+pub struct Locked<T, L>
+where
+    T: Deref<Target: Lockable>,
+    L: LockKind,
+{
     inner: T,
     _lock: PhantomData<L>,
 }
 
-impl<T, L> Locked<T, L> {
-    pub fn new(inner: T) -> Self {
+impl<T, L> Locked<T, L>
+where
+    T: Deref<Target: Lockable>,
+    L: LockKind,
+{
+    /// Acquires the semaphore of `inner`, sleeping until it is available.
+    pub fn acquire(inner: T) -> Self {
+        L::acquire(inner.rwsem());
+
         Self {
             inner,
             _lock: PhantomData,
@@ -17,17 +115,195 @@ impl<T, L> Locked<T, L> {
     }
 }
 
-impl<T, L> core::ops::Deref for Locked<T, L> {
+impl<T, L> Deref for Locked<T, L>
+where
+    T: Deref<Target: Lockable>,
+    L: LockKind,
+{
     type Target = T;
+
     fn deref(&self) -> &T {
         &self.inner
     }
 }
 
-pub struct ReadSem;
-pub struct WriteSem;
+impl<T> DerefMut for Locked<T, WriteSem>
+where
+    T: Deref<Target: Lockable>,
+{
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T, L> Drop for Locked<T, L>
+where
+    T: Deref<Target: Lockable>,
+    L: LockKind,
+{
+    fn drop(&mut self) {
+        L::release(self.inner.rwsem());
+    }
+}
 
+/// A positive errno value; see [`code`] for the known ones.
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Error(pub i32);
 
+impl Error {
+    /// Converts a negative errno as returned by kernel functions, falling back to `EINVAL` for
+    /// values that are not errors.
+    pub fn from_errno(errno: i32) -> Self {
+        if (-4095..0).contains(&errno) {
+            Self(-errno)
+        } else {
+            code::EINVAL
+        }
+    }
+
+    /// The negative errno, as returned to userspace.
+    pub fn to_errno(self) -> i32 {
+        -self.0
+    }
+
+    /// The symbolic name, such as `"ENOENT"`.
+    pub fn name(self) -> Option<&'static str> {
+        code::describe(self.0).map(|(name, _)| name)
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => f.debug_tuple("Error").field(&self.0).finish(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match code::describe(self.0) {
+            Some((name, desc)) => write!(f, "{name}: {}", desc.trim_end_matches('.')),
+            None => write!(f, "Unknown error {}", self.0),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<core::num::TryFromIntError> for Error {
+    fn from(_: core::num::TryFromIntError) -> Self {
+        code::EINVAL
+    }
+}
+
+impl From<core::num::ParseIntError> for Error {
+    fn from(_: core::num::ParseIntError) -> Self {
+        code::EINVAL
+    }
+}
+
+impl From<core::str::Utf8Error> for Error {
+    fn from(_: core::str::Utf8Error) -> Self {
+        code::EINVAL
+    }
+}
+
+impl From<core::alloc::LayoutError> for Error {
+    fn from(_: core::alloc::LayoutError) -> Self {
+        code::ENOMEM
+    }
+}
+
+/// A poisoned lock means another thread panicked half-way through an update.
+impl<T> From<PoisonError<T>> for Error {
+    fn from(_: PoisonError<T>) -> Self {
+        code::EIO
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        use std::io::ErrorKind;
+
+        if let Some(errno) = e.raw_os_error() {
+            return Self(errno);
+        }
+
+        match e.kind() {
+            ErrorKind::NotFound => code::ENOENT,
+            ErrorKind::PermissionDenied => code::EACCES,
+            ErrorKind::AlreadyExists => code::EEXIST,
+            ErrorKind::InvalidInput => code::EINVAL,
+            ErrorKind::OutOfMemory => code::ENOMEM,
+            ErrorKind::Unsupported => code::EOPNOTSUPP,
+            _ => code::EIO,
+        }
+    }
+}
+
 pub type Result<T = (), E = Error> = core::result::Result<T, E>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[derive(Default)]
+    struct Object(RwSemaphore);
+
+    impl Lockable for Object {
+        fn rwsem(&self) -> &RwSemaphore {
+            &self.0
+        }
+    }
+
+    #[test]
+    fn readers_share_and_writers_exclude() {
+        let obj = Object::default();
+        let written = AtomicBool::new(false);
+
+        let r1 = Locked::<_, ReadSem>::acquire(&obj);
+        let r2 = Locked::<_, ReadSem>::acquire(&obj);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _w = Locked::<_, WriteSem>::acquire(&obj);
+                written.store(true, Ordering::SeqCst);
+            });
+
+            thread::sleep(Duration::from_millis(20));
+            assert!(!written.load(Ordering::SeqCst));
+
+            drop(r1);
+            drop(r2);
+        });
+
+        assert!(written.load(Ordering::SeqCst));
+        assert_eq!(*obj.0.count.lock().unwrap(), 0);
+    }
+
+    #[test]
+    fn symbolic_names() {
+        assert_eq!(format!("{:?}", code::ENOENT), "ENOENT");
+        assert_eq!(format!("{}", code::EISDIR), "EISDIR: Is a directory");
+        assert_eq!(format!("{:?}", Error(4000)), "Error(4000)");
+        assert_eq!(code::ENOTSUPP.name(), Some("ENOTSUPP"));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(Error::from_errno(-2), code::ENOENT);
+        assert_eq!(Error::from_errno(3), code::EINVAL);
+        assert_eq!(code::EIO.to_errno(), -5);
+
+        let e: Error = u8::try_from(300u32).unwrap_err().into();
+        assert_eq!(e, code::EINVAL);
+
+        let e: Error = std::io::Error::from_raw_os_error(28).into();
+        assert_eq!(e, code::ENOSPC);
+    }
+}
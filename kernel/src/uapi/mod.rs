@@ -0,0 +1,81 @@
+//! Types and constants shared with userspace.
+
+#![allow(non_camel_case_types)]
+
+pub type uid_t = u32;
+pub type gid_t = u32;
+pub type mode_t = u32;
+/// The 16-bit mode that inodes store.
+pub type umode_t = u16;
+
+pub const S_IFMT: mode_t = 0o170000;
+pub const S_IFSOCK: mode_t = 0o140000;
+pub const S_IFLNK: mode_t = 0o120000;
+pub const S_IFREG: mode_t = 0o100000;
+pub const S_IFBLK: mode_t = 0o060000;
+pub const S_IFDIR: mode_t = 0o040000;
+pub const S_IFCHR: mode_t = 0o020000;
+pub const S_IFIFO: mode_t = 0o010000;
+
+pub const S_ISUID: mode_t = 0o4000;
+pub const S_ISGID: mode_t = 0o2000;
+pub const S_ISVTX: mode_t = 0o1000;
+
+pub const S_IRWXU: mode_t = 0o700;
+pub const S_IRUSR: mode_t = 0o400;
+pub const S_IWUSR: mode_t = 0o200;
+pub const S_IXUSR: mode_t = 0o100;
+pub const S_IRWXG: mode_t = 0o070;
+pub const S_IRGRP: mode_t = 0o040;
+pub const S_IWGRP: mode_t = 0o020;
+pub const S_IXGRP: mode_t = 0o010;
+pub const S_IRWXO: mode_t = 0o007;
+pub const S_IROTH: mode_t = 0o004;
+pub const S_IWOTH: mode_t = 0o002;
+pub const S_IXOTH: mode_t = 0o001;
+
+/// The permission and set-id bits of a mode, everything but the file type.
+pub const S_IALLUGO: mode_t = S_ISUID | S_ISGID | S_ISVTX | S_IRWXU | S_IRWXG | S_IRWXO;
+
+pub const O_ACCMODE: u32 = 0o3;
+pub const O_RDONLY: u32 = 0o0;
+pub const O_WRONLY: u32 = 0o1;
+pub const O_RDWR: u32 = 0o2;
+pub const O_CREAT: u32 = 0o100;
+pub const O_EXCL: u32 = 0o200;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_APPEND: u32 = 0o2000;
+pub const O_DIRECTORY: u32 = 0o200000;
+
+pub const fn s_isdir(mode: mode_t) -> bool {
+    mode & S_IFMT == S_IFDIR
+}
+
+pub const fn s_isreg(mode: mode_t) -> bool {
+    mode & S_IFMT == S_IFREG
+}
+
+pub const fn s_islnk(mode: mode_t) -> bool {
+    mode & S_IFMT == S_IFLNK
+}
+
+pub const fn s_ischr(mode: mode_t) -> bool {
+    mode & S_IFMT == S_IFCHR
+}
+
+pub const fn s_isblk(mode: mode_t) -> bool {
+    mode & S_IFMT == S_IFBLK
+}
+
+pub const fn s_isfifo(mode: mode_t) -> bool {
+    mode & S_IFMT == S_IFIFO
+}
+
+pub const fn s_issock(mode: mode_t) -> bool {
+    mode & S_IFMT == S_IFSOCK
+}
+
+/// Replaces the permission bits of `mode` with those of `perm`, keeping the file type.
+pub const fn with_perm(mode: mode_t, perm: mode_t) -> mode_t {
+    (mode & S_IFMT) | (perm & S_IALLUGO)
+}
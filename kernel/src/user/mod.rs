@@ -0,0 +1,165 @@
+use crate::types::Result;
+use crate::types::code::EFAULT;
+
+/// A buffer supplied by userspace that the kernel copies data out of (`copy_from_user`).
+///
+/// Copies may stop short of the end of the buffer if part of it is not mapped, which tests model
+/// with [`Reader::faulting_at`].
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    fault: Option<usize>,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            fault: None,
+        }
+    }
+
+    /// Makes every access at or past `offset` into the buffer fault.
+    pub fn faulting_at(self, offset: usize) -> Self {
+        Self {
+            fault: Some(offset),
+            ..self
+        }
+    }
+
+    /// Number of bytes left to read, including any that fault.
+    pub fn len(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of bytes read so far.
+    pub fn read(&self) -> usize {
+        self.pos
+    }
+
+    /// How many of the next `len` bytes can be read without faulting (`fault_in_readable`).
+    pub fn fault_in(&self, len: usize) -> usize {
+        let end = self.fault.unwrap_or(usize::MAX).min(self.buf.len());
+
+        end.saturating_sub(self.pos).min(len)
+    }
+
+    /// Fills as much of `out` as can be read, returning how many bytes were copied.
+    pub fn read_partial(&mut self, out: &mut [u8]) -> usize {
+        let n = self.fault_in(out.len());
+
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+
+        n
+    }
+
+    /// Fills `out` from the buffer, failing with `EFAULT` and reading nothing if it cannot be
+    /// filled completely.
+    pub fn read_slice(&mut self, out: &mut [u8]) -> Result {
+        if self.fault_in(out.len()) < out.len() {
+            return Err(EFAULT);
+        }
+
+        self.read_partial(out);
+        Ok(())
+    }
+}
+
+/// A buffer supplied by userspace that the kernel copies data into (`copy_to_user`).
+///
+/// Copies may stop short of the end of the buffer if part of it is not mapped, which tests model
+/// with [`Writer::faulting_at`].
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    written: usize,
+    fault: Option<usize>,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            written: 0,
+            fault: None,
+        }
+    }
+
+    /// Makes every access at or past `offset` into the buffer fault.
+    pub fn faulting_at(self, offset: usize) -> Self {
+        Self {
+            fault: Some(offset),
+            ..self
+        }
+    }
+
+    /// Number of bytes that can still be written, including any that fault.
+    pub fn len(&self) -> usize {
+        self.buf.len() - self.written
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of bytes written so far.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Copies as much of `data` as can be written, returning how many bytes were copied.
+    pub fn write_partial(&mut self, data: &[u8]) -> usize {
+        let end = self.fault.unwrap_or(usize::MAX).min(self.buf.len());
+        let n = end.saturating_sub(self.written).min(data.len());
+
+        self.buf[self.written..self.written + n].copy_from_slice(&data[..n]);
+        self.written += n;
+
+        n
+    }
+
+    /// Copies all of `data` into the buffer, failing with `EFAULT` if it does not fit.
+    ///
+    /// On failure, the bytes before the fault may have been written already.
+    pub fn write_slice(&mut self, data: &[u8]) -> Result {
+        if self.write_partial(data) < data.len() {
+            return Err(EFAULT);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_stop_at_the_fault() {
+        let data: Vec<u8> = (0..8).collect();
+
+        let mut reader = Reader::new(&data).faulting_at(5);
+        let mut out = [0; 4];
+        assert_eq!(reader.fault_in(4), 4);
+        assert_eq!(reader.read_slice(&mut out), Ok(()));
+        assert_eq!(reader.read_slice(&mut out), Err(EFAULT));
+        assert_eq!(reader.read(), 4);
+        assert_eq!(reader.read_partial(&mut out), 1);
+        assert_eq!((out[0], reader.len(), reader.fault_in(8)), (4, 3, 0));
+
+        let mut buf = [0; 8];
+        let mut writer = Writer::new(&mut buf).faulting_at(3);
+        assert_eq!(writer.write_partial(&data[..2]), 2);
+        assert_eq!(writer.write_slice(&data[..2]), Err(EFAULT));
+        assert_eq!((writer.written(), writer.len()), (3, 5));
+        assert_eq!(buf, [0, 1, 0, 0, 0, 0, 0, 0]);
+
+        let mut small = [0; 2];
+        assert_eq!(Writer::new(&mut small).write_slice(&data), Err(EFAULT));
+    }
+}
@@ -0,0 +1,484 @@
+//! A path-based view of one mounted filesystem, the userspace end of the file system calls.
+
+use std::sync::Arc;
+
+use crate::block::BlockDevice;
+use crate::dentry::{self, DEntry};
+use crate::file::{DirEmitter, DirEntry, File};
+use crate::fs::{self, FileSystem, Offset};
+use crate::inode::{self, Attr, INode, MAY_READ, MAY_WRITE, Stat};
+use crate::sb::SuperBlock;
+use crate::types::code::{EBADF, EEXIST, EINVAL, EISDIR, ENOENT, ENOTDIR, ENOTSUPP, EROFS};
+use crate::types::{ARef, Result};
+use crate::uapi::{
+    self, O_ACCMODE, O_APPEND, O_CREAT, O_DIRECTORY, O_EXCL, O_RDONLY, O_TRUNC, O_WRONLY, S_IFDIR,
+    S_IFREG, mode_t,
+};
+use crate::user;
+
+/// A mounted filesystem whose files are reached by path.
+///
+/// Paths are `/`-separated and resolved from the root of the mount, whether or not they start
+/// with `/`. Changes to the namespace keep the dentry cache in step with the filesystem.
+pub struct Vfs<T: FileSystem> {
+    sb: SuperBlock<T>,
+    root: ARef<DEntry<T>>,
+}
+
+impl<T: FileSystem> Vfs<T> {
+    /// Mounts `T` on `device` with the options in `data`.
+    pub fn mount(device: Option<Arc<dyn BlockDevice>>, data: &str) -> Result<Self> {
+        let sb = fs::mount::<T>(device, data)?;
+        let root = sb.root().ok_or(EINVAL)?;
+
+        Ok(Self { sb, root })
+    }
+
+    /// Unmounts the filesystem, writing everything back.
+    ///
+    /// Files still open keep their inodes until they are closed.
+    pub fn umount(self) -> Result {
+        drop(self.root);
+        fs::kill_sb(self.sb)
+    }
+
+    pub fn super_block(&self) -> &SuperBlock<T> {
+        &self.sb
+    }
+
+    pub fn root(&self) -> &ARef<DEntry<T>> {
+        &self.root
+    }
+
+    fn check_writable(&self) -> Result {
+        if self.sb.is_rdonly() {
+            return Err(EROFS);
+        }
+
+        Ok(())
+    }
+
+    /// Resolves `path` to a dentry, which may be negative.
+    fn lookup(&self, path: &[u8]) -> Result<ARef<DEntry<T>>> {
+        dentry::walk(&self.root, path)
+    }
+
+    /// Resolves `path` to an existing inode.
+    fn inode(&self, path: &[u8]) -> Result<ARef<INode<T>>> {
+        self.lookup(path)?.inode().ok_or(ENOENT)
+    }
+
+    /// Splits `path` into the dentry of its parent directory and its last component.
+    fn parent<'p>(&self, path: &'p [u8]) -> Result<(ARef<DEntry<T>>, &'p [u8])> {
+        let end = path.iter().rposition(|&b| b != b'/').map_or(0, |i| i + 1);
+        let path = &path[..end];
+
+        let (dir, name) = match path.iter().rposition(|&b| b == b'/') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => (&path[..0], path),
+        };
+
+        let parent = self.lookup(dir)?;
+        if parent.is_negative() {
+            return Err(ENOENT);
+        }
+
+        Ok((parent, name))
+    }
+
+    /// Fails with `EINVAL` for names that cannot be created or removed: `.`, `..` and none.
+    fn check_name(name: &[u8]) -> Result {
+        if matches!(name, b"" | b"." | b"..") {
+            return Err(EINVAL);
+        }
+
+        Ok(())
+    }
+
+    /// Looks up `name` in the directory `dir`, failing with `ENOENT` if it does not exist.
+    pub fn lookup_at(&self, dir: &ARef<DEntry<T>>, name: &[u8]) -> Result<ARef<DEntry<T>>> {
+        let dentry = dentry::lookup_one(dir, name)?;
+        if dentry.is_negative() {
+            return Err(ENOENT);
+        }
+
+        Ok(dentry)
+    }
+
+    /// Creates the regular file `name` in `dir` with permissions `mode`.
+    pub fn create_at(
+        &self,
+        dir: &ARef<DEntry<T>>,
+        name: &[u8],
+        mode: mode_t,
+    ) -> Result<ARef<DEntry<T>>> {
+        self.check_writable()?;
+        Self::check_name(name)?;
+
+        if !dentry::lookup_one(dir, name)?.is_negative() {
+            return Err(EEXIST);
+        }
+
+        let inode = dir.inode().ok_or(ENOENT)?;
+        let child = inode
+            .ops()
+            .create(&inode.lock(), name, uapi::with_perm(S_IFREG, mode))?;
+
+        Ok(dir.d_add(name, Some(child)))
+    }
+
+    /// Creates the directory `name` in `dir` with permissions `mode`.
+    pub fn mkdir_at(
+        &self,
+        dir: &ARef<DEntry<T>>,
+        name: &[u8],
+        mode: mode_t,
+    ) -> Result<ARef<DEntry<T>>> {
+        self.check_writable()?;
+        Self::check_name(name)?;
+
+        if !dentry::lookup_one(dir, name)?.is_negative() {
+            return Err(EEXIST);
+        }
+
+        let inode = dir.inode().ok_or(ENOENT)?;
+        let child = inode
+            .ops()
+            .mkdir(&inode.lock(), name, uapi::with_perm(S_IFDIR, mode))?;
+
+        Ok(dir.d_add(name, Some(child)))
+    }
+
+    /// Removes the non-directory `name` from `dir`.
+    pub fn unlink_at(&self, dir: &ARef<DEntry<T>>, name: &[u8]) -> Result {
+        self.check_writable()?;
+        Self::check_name(name)?;
+
+        let inode = dir.inode().ok_or(ENOENT)?;
+        inode.ops().unlink(&inode.lock(), name)?;
+
+        if let Some(dentry) = dir.cached(name) {
+            dentry.d_drop();
+        }
+
+        Ok(())
+    }
+
+    /// Removes the empty directory `name` from `dir`.
+    pub fn rmdir_at(&self, dir: &ARef<DEntry<T>>, name: &[u8]) -> Result {
+        self.check_writable()?;
+        Self::check_name(name)?;
+
+        let inode = dir.inode().ok_or(ENOENT)?;
+        inode.ops().rmdir(&inode.lock(), name)?;
+
+        if let Some(dentry) = dir.cached(name) {
+            dentry.d_drop();
+        }
+
+        Ok(())
+    }
+
+    /// Moves `old_name` in `old_dir` to `new_name` in `new_dir`, replacing whatever was there.
+    ///
+    /// Fails with `EINVAL` if `new_dir` is below the entry being moved.
+    pub fn rename_at(
+        &self,
+        old_dir: &ARef<DEntry<T>>,
+        old_name: &[u8],
+        new_dir: &ARef<DEntry<T>>,
+        new_name: &[u8],
+    ) -> Result {
+        self.check_writable()?;
+        Self::check_name(old_name)?;
+        Self::check_name(new_name)?;
+
+        let moved = self.lookup_at(old_dir, old_name)?;
+
+        let mut ancestor = Some(new_dir);
+        while let Some(dentry) = ancestor {
+            if ARef::ptr_eq(dentry, &moved) {
+                return Err(EINVAL);
+            }
+
+            ancestor = dentry.parent();
+        }
+
+        let old_inode = old_dir.inode().ok_or(ENOENT)?;
+        let new_inode = new_dir.inode().ok_or(ENOENT)?;
+        let ops = old_inode.ops();
+
+        if old_inode.ino() == new_inode.ino() {
+            let locked = old_inode.lock();
+            ops.rename(&locked, old_name, &locked, new_name, 0)?;
+        } else {
+            // Lock in inode order so that concurrent renames cannot deadlock.
+            let (old_locked, new_locked) = if old_inode.ino() < new_inode.ino() {
+                let old_locked = old_inode.lock();
+                (old_locked, new_inode.lock())
+            } else {
+                let new_locked = new_inode.lock();
+                (old_inode.lock(), new_locked)
+            };
+
+            ops.rename(&old_locked, old_name, &new_locked, new_name, 0)?;
+        }
+
+        moved.d_drop();
+        if let Some(dentry) = new_dir.cached(new_name) {
+            dentry.d_drop();
+        }
+
+        Ok(())
+    }
+
+    /// Checks the access that `flags` ask for against the permissions of `inode`, as `may_open`
+    /// does.
+    fn may_open(inode: &INode<T>, flags: u32) -> Result {
+        let mut mask = match flags & O_ACCMODE {
+            O_RDONLY => MAY_READ,
+            O_WRONLY => MAY_WRITE,
+            _ => MAY_READ | MAY_WRITE,
+        };
+
+        if flags & O_TRUNC != 0 {
+            mask |= MAY_WRITE;
+        }
+
+        inode::generic_permission(inode, mask)
+    }
+
+    /// Opens the inode `dentry` refers to with the `O_*` flags in `flags`; `O_CREAT` and `O_EXCL`
+    /// are ignored.
+    pub fn open_dentry(&self, dentry: ARef<DEntry<T>>, flags: u32) -> Result<File<T>> {
+        self.do_open(dentry, flags, false)
+    }
+
+    /// Opens `dentry`, without checking permissions if the file was just `created`: its mode
+    /// does not restrict the open that creates it.
+    fn do_open(&self, dentry: ARef<DEntry<T>>, flags: u32, created: bool) -> Result<File<T>> {
+        if flags & O_ACCMODE != O_RDONLY || flags & O_TRUNC != 0 {
+            self.check_writable()?;
+        }
+
+        let inode = dentry.inode().ok_or(ENOENT)?;
+
+        if uapi::s_isdir(inode.mode()) {
+            if flags & O_ACCMODE != O_RDONLY {
+                return Err(EISDIR);
+            }
+        } else if flags & O_DIRECTORY != 0 {
+            return Err(ENOTDIR);
+        }
+
+        if !created {
+            Self::may_open(&inode, flags)?;
+        }
+
+        if flags & O_TRUNC != 0 && uapi::s_isreg(inode.mode()) && inode.size() != 0 {
+            let attr = Attr {
+                size: Some(0),
+                ..Attr::default()
+            };
+
+            inode.ops().setattr(&inode.lock(), &attr)?;
+        }
+
+        Ok(File::open(dentry)?.with_flags(flags))
+    }
+
+    /// Opens the file at `path` with the `O_*` flags in `flags`, creating it with permissions
+    /// `mode` if `O_CREAT` is given and it does not exist.
+    pub fn open(&self, path: &[u8], flags: u32, mode: mode_t) -> Result<File<T>> {
+        let (dentry, created) = if flags & O_CREAT != 0 {
+            let (parent, name) = self.parent(path)?;
+
+            match dentry::lookup_one(&parent, name)? {
+                dentry if dentry.is_negative() => (self.create_at(&parent, name, mode)?, true),
+                _ if flags & O_EXCL != 0 => return Err(EEXIST),
+                dentry => (dentry, false),
+            }
+        } else {
+            (self.lookup(path)?, false)
+        };
+
+        self.do_open(dentry, flags, created)
+    }
+
+    /// Reads into `buf` from `offset` in `file`, as `pread(2)` does.
+    pub fn read_at(&self, file: &File<T>, buf: &mut [u8], offset: Offset) -> Result<usize> {
+        if file.flags() & O_ACCMODE == O_WRONLY {
+            return Err(EBADF);
+        }
+
+        let mut pos = offset;
+        file.inode()
+            .fops()
+            .read(file, &mut user::Writer::new(buf), &mut pos)
+    }
+
+    /// Writes `buf` at `offset` in `file`, as `pwrite(2)` does.
+    pub fn write_at(&self, file: &File<T>, buf: &[u8], offset: Offset) -> Result<usize> {
+        if file.flags() & O_ACCMODE == O_RDONLY {
+            return Err(EBADF);
+        }
+
+        self.check_writable()?;
+
+        let mut pos = offset;
+        file.inode()
+            .fops()
+            .write(file, &mut user::Reader::new(buf), &mut pos)
+    }
+
+    /// Reads into `buf` at the position of `file` and advances it, as `read(2)` does.
+    pub fn read(&self, file: &File<T>, buf: &mut [u8]) -> Result<usize> {
+        let read = self.read_at(file, buf, file.pos())?;
+        file.set_pos(file.pos() + read as Offset);
+
+        Ok(read)
+    }
+
+    /// Writes `buf` at the position of `file`, or at its end with `O_APPEND`, and advances it, as
+    /// `write(2)` does.
+    pub fn write(&self, file: &File<T>, buf: &[u8]) -> Result<usize> {
+        let pos = if file.flags() & O_APPEND != 0 {
+            file.inode().size().try_into()?
+        } else {
+            file.pos()
+        };
+
+        let written = self.write_at(file, buf, pos)?;
+        file.set_pos(pos + written as Offset);
+
+        Ok(written)
+    }
+
+    /// Lists the directory open as `file` from `pos`, in entries worth up to `space` bytes of
+    /// `linux_dirent64` records, as `getdents64(2)` does.
+    pub fn read_dir(&self, file: &File<T>, pos: Offset, space: usize) -> Result<Vec<DirEntry>> {
+        let inode = file.inode();
+        let mut emitter = DirEmitter::new(pos, space);
+
+        inode
+            .fops()
+            .read_dir(file, &inode.lock_shared(), &mut emitter)?;
+
+        Ok(emitter.into_entries())
+    }
+
+    /// Creates the directory `path` with permissions `mode`.
+    pub fn mkdir(&self, path: &[u8], mode: mode_t) -> Result {
+        let (parent, name) = self.parent(path)?;
+        self.mkdir_at(&parent, name, mode).map(drop)
+    }
+
+    /// Lists the directory `path`, including `.` and `..`.
+    pub fn readdir(&self, path: &[u8]) -> Result<Vec<DirEntry>> {
+        let file = File::open(self.lookup(path)?)?;
+        let mut entries = Vec::new();
+
+        loop {
+            let batch = self.read_dir(&file, file.pos(), 4096)?;
+
+            match batch.last() {
+                Some(last) => file.set_pos(last.pos),
+                None => return Ok(entries),
+            }
+
+            entries.extend(batch);
+        }
+    }
+
+    /// Removes the non-directory `path`.
+    pub fn unlink(&self, path: &[u8]) -> Result {
+        let (parent, name) = self.parent(path)?;
+        self.unlink_at(&parent, name)
+    }
+
+    /// Removes the empty directory `path`.
+    pub fn rmdir(&self, path: &[u8]) -> Result {
+        let (parent, name) = self.parent(path)?;
+        self.rmdir_at(&parent, name)
+    }
+
+    /// Moves `old` to `new`, replacing whatever `new` was.
+    ///
+    /// Fails with `EINVAL` if `new` is below `old`.
+    pub fn rename(&self, old: &[u8], new: &[u8]) -> Result {
+        let (old_parent, old_name) = self.parent(old)?;
+        let (new_parent, new_name) = self.parent(new)?;
+
+        self.rename_at(&old_parent, old_name, &new_parent, new_name)
+    }
+
+    /// The attributes of `inode`.
+    pub fn getattr(&self, inode: &INode<T>) -> Result<Stat> {
+        match inode.ops().getattr(inode) {
+            Err(ENOTSUPP) => Ok(inode::generic_fillattr(inode)),
+            res => res,
+        }
+    }
+
+    /// Changes the attributes of `inode`.
+    pub fn setattr(&self, inode: &INode<T>, attr: &Attr) -> Result {
+        self.check_writable()?;
+        inode.ops().setattr(&inode.lock(), attr)
+    }
+
+    /// The attributes of `path`.
+    pub fn stat(&self, path: &[u8]) -> Result<Stat> {
+        let inode = self.inode(path)?;
+        self.getattr(&inode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cred::{self, Credentials};
+    use crate::testing::TestFs;
+    use crate::types::code::{EACCES, EPERM};
+    use crate::uapi::O_RDWR;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn paths_resolve_through_the_dentry_cache() {
+        let vfs = Vfs::<TestFs>::mount(None, "").unwrap();
+
+        assert_eq!(vfs.stat(b"/").unwrap().ino, 1);
+        assert!(uapi::s_isdir(vfs.stat(b"/dir/").unwrap().mode));
+        assert_eq!(vfs.stat(b"dir/file").unwrap().mode, S_IFREG | 0o644);
+        assert_eq!(vfs.stat(b"dir/missing").err(), Some(ENOENT));
+        assert_eq!(vfs.stat(b"file/x").err(), Some(ENOTDIR));
+        assert_eq!(vfs.super_block().data().load(Ordering::Relaxed), 4);
+
+        assert_eq!(vfs.open(b"dir", O_RDWR, 0).err(), Some(EISDIR));
+        assert_eq!(vfs.open(b"file", O_DIRECTORY, 0).err(), Some(ENOTDIR));
+        assert_eq!(vfs.readdir(b"file").err(), Some(ENOTDIR));
+        assert_eq!(vfs.mkdir(b"dir/..", 0o755), Err(EINVAL));
+        assert_eq!(vfs.mkdir(b"dir/file", 0o755), Err(EEXIST));
+        assert_eq!(vfs.mkdir(b"dir/missing", 0o755), Err(EPERM));
+
+        let file = vfs.open(b"file", O_RDONLY, 0).unwrap();
+        assert_eq!(vfs.write(&file, b"x"), Err(EBADF));
+        drop(file);
+
+        let user = cred::override_creds(Arc::new(Credentials::user(1000, 1000)));
+        assert!(vfs.open(b"dir/file", O_RDONLY, 0).is_ok());
+        assert_eq!(vfs.open(b"dir/file", O_WRONLY, 0).err(), Some(EACCES));
+        assert_eq!(vfs.open(b"dir/file", O_RDWR, 0).err(), Some(EACCES));
+        assert_eq!(
+            vfs.open(b"dir/file", O_RDONLY | O_TRUNC, 0).err(),
+            Some(EACCES)
+        );
+        drop(user);
+        vfs.umount().unwrap();
+
+        let vfs = Vfs::<TestFs>::mount(None, "ro").unwrap();
+        assert_eq!(vfs.open(b"file", O_WRONLY, 0).err(), Some(EROFS));
+        assert_eq!(vfs.unlink(b"file"), Err(EROFS));
+        assert_eq!(vfs.rename(b"file", b"dir/file"), Err(EROFS));
+        vfs.umount().unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+//! Writeback of dirty inodes and pages, the equivalent of `fs/fs-writeback.c` and `fs/sync.c`.
+
+use crate::address_space;
+use crate::fs::FileSystem;
+use crate::inode::INode;
+use crate::sb::SuperBlock;
+use crate::types::Result;
+
+/// Writes back the dirty pages of `inode` and then, if it is dirty, the inode itself.
+///
+/// On failure the inode is marked dirty again so that a later writeback retries it. Nothing is
+/// written back while the superblock is read-only.
+pub fn write_inode_now<T: FileSystem + ?Sized>(inode: &INode<T>, wait: bool) -> Result {
+    if inode.super_block().is_rdonly() {
+        return Ok(());
+    }
+
+    let dirty = inode.clear_dirty();
+
+    let res = address_space::filemap_write_and_wait(inode).and_then(|()| {
+        if dirty {
+            inode.super_block().ops().write_inode(inode, wait)
+        } else {
+            Ok(())
+        }
+    });
+
+    if res.is_err() {
+        inode.mark_dirty();
+    }
+
+    res
+}
+
+/// Writes back every inode on the dirty list of `sb`, returning the first error.
+pub fn writeback_inodes_sb<T: FileSystem>(sb: &SuperBlock<T>, wait: bool) -> Result {
+    let mut res = Ok(());
+
+    for inode in sb.take_dirty_inodes()? {
+        let written = write_inode_now(&inode, wait);
+        res = res.and(written);
+    }
+
+    res
+}
+
+/// Writes back everything dirty in `sb`, including the buffers in use, and flushes its device, the
+/// equivalent of `sync_filesystem`.
+///
+/// Like the kernel, this makes a pass that does not wait followed by one that does, and does
+/// nothing on a read-only superblock.
+pub fn sync_filesystem<T: FileSystem>(sb: &SuperBlock<T>) -> Result {
+    if sb.is_rdonly() {
+        return Ok(());
+    }
+
+    let ops = sb.ops();
+
+    writeback_inodes_sb(sb, false)?;
+    ops.sync_fs(sb, false)?;
+
+    writeback_inodes_sb(sb, true)?;
+    ops.sync_fs(sb, true)?;
+    sb.buffers().sync()?;
+
+    match sb.device() {
+        Some(device) => device.flush(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sb::SB_RDONLY;
+    use crate::testing::{INODES_WRITTEN, new_inode, super_block};
+
+    fn written() -> usize {
+        INODES_WRITTEN.with(|n| n.get())
+    }
+
+    #[test]
+    fn sync_writes_dirty_inodes_once() {
+        let sb = super_block();
+        let inode = new_inode(&sb, 2);
+        let before = written();
+
+        inode.mark_dirty();
+        inode.mark_dirty();
+        assert!(inode.is_dirty());
+        assert_eq!(sb.dirty_inodes(), 1);
+
+        sync_filesystem(&sb).unwrap();
+        assert_eq!(written(), before + 1);
+        assert!(!inode.is_dirty());
+        assert_eq!(sb.dirty_inodes(), 0);
+
+        sync_filesystem(&sb).unwrap();
+        assert_eq!(written(), before + 1);
+    }
+
+    #[test]
+    fn evicting_a_dirty_inode_writes_it_back() {
+        let sb = super_block();
+        let inode = new_inode(&sb, 2);
+        let before = written();
+
+        inode.mark_dirty();
+        drop(inode);
+        assert_eq!(written(), before + 1);
+        assert_eq!(sb.dirty_inodes(), 0);
+
+        // Inodes without links are not written back.
+        let inode = new_inode(&sb, 3);
+        inode.set_nlink(0);
+        inode.mark_dirty();
+        drop(inode);
+        assert_eq!(written(), before + 1);
+    }
+
+    #[test]
+    fn read_only_superblocks_are_not_written_back() {
+        let sb = super_block();
+        let inode = new_inode(&sb, 2);
+        let before = written();
+
+        sb.update_flags(SB_RDONLY);
+        inode.mark_dirty();
+        sync_filesystem(&sb).unwrap();
+        drop(inode);
+        assert_eq!(written(), before);
+    }
+}